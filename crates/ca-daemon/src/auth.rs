@@ -0,0 +1,87 @@
+//! Opt-in shared-secret authentication for the socket.
+//!
+//! With `--require-auth`, the daemon loads a token from the token file at
+//! startup and every connection must open with `RpcRequest::Auth { token }`.
+//! The socket is already `0600`; the token guards shared machines where other
+//! processes run as the same user.
+
+use std::path::Path;
+
+use anyhow::{Context, Result, bail};
+
+/// Read the token at `path`, refusing (on Unix) files readable by group or
+/// others. Surrounding whitespace (a trailing newline from `echo`) is ignored.
+pub fn load_token(path: &Path) -> Result<String> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        let meta =
+            std::fs::metadata(path).with_context(|| format!("reading token {}", path.display()))?;
+        let mode = meta.permissions().mode() & 0o777;
+        if mode & 0o077 != 0 {
+            bail!(
+                "token file {} has mode {mode:o}; expected 0600",
+                path.display()
+            );
+        }
+    }
+    let raw = std::fs::read_to_string(path)
+        .with_context(|| format!("reading token {}", path.display()))?;
+    let token = raw.trim();
+    if token.is_empty() {
+        bail!("token file {} is empty", path.display());
+    }
+    Ok(token.to_owned())
+}
+
+/// Compare two secrets without short-circuiting on the first differing byte.
+/// Only the length leaks.
+pub fn tokens_match(expected: &str, presented: &str) -> bool {
+    let (a, b) = (expected.as_bytes(), presented.as_bytes());
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(unix)]
+    use std::os::unix::fs::PermissionsExt;
+
+    use super::*;
+
+    #[test]
+    fn tokens_match_only_on_exact_equality() {
+        assert!(tokens_match("s3cret", "s3cret"));
+        assert!(!tokens_match("s3cret", "s3creT"));
+        assert!(!tokens_match("s3cret", "s3cret "));
+        assert!(!tokens_match("s3cret", ""));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn load_token_trims_and_requires_private_mode() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("ca.token");
+        std::fs::write(&path, "abc123\n").unwrap();
+
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o644)).unwrap();
+        let err = load_token(&path).unwrap_err();
+        assert!(err.to_string().contains("expected 0600"), "{err:#}");
+
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600)).unwrap();
+        assert_eq!(load_token(&path).unwrap(), "abc123");
+    }
+
+    #[test]
+    fn load_token_rejects_empty_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("ca.token");
+        std::fs::write(&path, "\n").unwrap();
+        #[cfg(unix)]
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600)).unwrap();
+        assert!(load_token(&path).is_err());
+    }
+}
@@ -0,0 +1,150 @@
+//! Wire framing for RPC messages.
+//!
+//! Two codecs share one connection handler: newline-delimited JSON (the
+//! default) and a length-prefixed mode (`u32` big-endian byte count followed
+//! by the JSON body) that tolerates raw newlines in payloads. A client opts
+//! into the latter with `RpcRequest::SetFraming`; the reply is still written
+//! in the old framing and the switch applies from the next message on.
+
+use std::io;
+
+use ca_lib::Framing;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt};
+
+/// Largest length-prefixed body accepted. Bigger prefixes are treated as a
+/// corrupt stream rather than an allocation request.
+pub const MAX_FRAME_LEN: u32 = 16 * 1024 * 1024;
+
+/// Read one frame's payload into `buf` (cleared first). Returns `Ok(false)`
+/// on a clean EOF at a frame boundary.
+pub async fn read_frame<R>(reader: &mut R, framing: Framing, buf: &mut Vec<u8>) -> io::Result<bool>
+where
+    R: AsyncBufRead + Unpin,
+{
+    buf.clear();
+    match framing {
+        Framing::Line => {
+            if reader.read_until(b'\n', buf).await? == 0 {
+                return Ok(false);
+            }
+            while matches!(buf.last(), Some(b'\n' | b'\r')) {
+                buf.pop();
+            }
+            Ok(true)
+        }
+        Framing::LengthPrefixed => {
+            if reader.fill_buf().await?.is_empty() {
+                return Ok(false);
+            }
+            let mut prefix = [0u8; 4];
+            reader.read_exact(&mut prefix).await?;
+            let len = u32::from_be_bytes(prefix);
+            if len > MAX_FRAME_LEN {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("frame of {len} bytes exceeds {MAX_FRAME_LEN}"),
+                ));
+            }
+            buf.resize(len as usize, 0);
+            reader.read_exact(buf).await?;
+            Ok(true)
+        }
+    }
+}
+
+/// Wrap `payload` in `framing`, ready for a single `write_all`.
+pub fn encode_frame(framing: Framing, payload: &[u8]) -> Vec<u8> {
+    match framing {
+        Framing::Line => {
+            let mut out = Vec::with_capacity(payload.len() + 1);
+            out.extend_from_slice(payload);
+            out.push(b'\n');
+            out
+        }
+        Framing::LengthPrefixed => {
+            let len = u32::try_from(payload.len()).expect("frame larger than 4 GiB");
+            let mut out = Vec::with_capacity(payload.len() + 4);
+            out.extend_from_slice(&len.to_be_bytes());
+            out.extend_from_slice(payload);
+            out
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn length_prefixed_roundtrips_payload_with_newlines() {
+        let payload = b"{\"text\":\"line one\nline two\n\"}";
+        let mut wire = encode_frame(Framing::LengthPrefixed, payload);
+        wire.extend(encode_frame(Framing::LengthPrefixed, b"{}"));
+
+        let mut reader = wire.as_slice();
+        let mut buf = Vec::new();
+        assert!(
+            read_frame(&mut reader, Framing::LengthPrefixed, &mut buf)
+                .await
+                .unwrap()
+        );
+        assert_eq!(buf, payload);
+        assert!(
+            read_frame(&mut reader, Framing::LengthPrefixed, &mut buf)
+                .await
+                .unwrap()
+        );
+        assert_eq!(buf, b"{}");
+        assert!(
+            !read_frame(&mut reader, Framing::LengthPrefixed, &mut buf)
+                .await
+                .unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn line_frames_strip_crlf() {
+        let mut reader: &[u8] = b"{\"type\":\"ping\"}\r\n{}";
+        let mut buf = Vec::new();
+        assert!(
+            read_frame(&mut reader, Framing::Line, &mut buf)
+                .await
+                .unwrap()
+        );
+        assert_eq!(buf, b"{\"type\":\"ping\"}");
+        // Trailing bytes without a newline still form a final frame.
+        assert!(
+            read_frame(&mut reader, Framing::Line, &mut buf)
+                .await
+                .unwrap()
+        );
+        assert_eq!(buf, b"{}");
+        assert!(
+            !read_frame(&mut reader, Framing::Line, &mut buf)
+                .await
+                .unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn oversized_prefix_is_invalid_data() {
+        let wire = (MAX_FRAME_LEN + 1).to_be_bytes();
+        let mut reader = wire.as_slice();
+        let err = read_frame(&mut reader, Framing::LengthPrefixed, &mut Vec::new())
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[tokio::test]
+    async fn truncated_body_is_an_error_not_eof() {
+        let mut wire = encode_frame(Framing::LengthPrefixed, b"{\"type\":\"ping\"}");
+        wire.truncate(8);
+        let mut reader = wire.as_slice();
+        assert!(
+            read_frame(&mut reader, Framing::LengthPrefixed, &mut Vec::new())
+                .await
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,327 @@
+//! Daemon configuration: command-line flags with env-var fallbacks.
+//!
+//! Flags win over env vars, env vars win over built-in defaults. The resolved
+//! [`Config`] is built once in `main` and shared read-only via
+//! [`crate::rpc::AppState`].
+
+use std::num::NonZeroUsize;
+use std::path::PathBuf;
+
+use clap::{Parser, ValueEnum};
+use serde_json::{Value, json};
+
+/// Resolved daemon configuration.
+#[derive(Debug, Clone, Parser)]
+#[command(
+    name = "ca-daemon",
+    version,
+    about = "Local orchestrator daemon for claude_admin"
+)]
+pub struct Config {
+    /// Directory holding the daemon's files (socket, token). Defaults to
+    /// `$HOME/.work`.
+    #[arg(long, env = "CA_DATA_DIR")]
+    pub data_dir: Option<PathBuf>,
+
+    /// Socket path. Defaults to `<data-dir>/ca.sock`, or
+    /// `<data-dir>/ca-<instance>.sock` with `--instance`. On Windows this is
+    /// a pipe name, defaulting to `\\.\pipe\ca[-<instance>]`.
+    #[arg(long, env = "CA_SOCKET_PATH")]
+    pub socket: Option<PathBuf>,
+
+    /// Instance name for running several daemons side by side. Suffixes the
+    /// default socket filename; an explicit `--socket` still wins.
+    #[arg(long, env = "CA_INSTANCE", value_parser = parse_instance)]
+    pub instance: Option<String>,
+
+    /// Per-connection request ceiling (token bucket, burst = one second's
+    /// worth). `0` disables the limit.
+    #[arg(long, default_value_t = 200)]
+    pub max_requests_per_sec: u32,
+
+    /// Maximum connections served at once. Clients past the limit get a
+    /// `busy` error and are disconnected. `0` disables the limit.
+    #[arg(long, default_value_t = 256)]
+    pub max_connections: u32,
+
+    /// Require every connection to open with an `auth` request carrying the
+    /// token from `--token-file`.
+    #[arg(long)]
+    pub require_auth: bool,
+
+    /// Token file used by `--require-auth`. Must be mode 0600. Defaults to
+    /// `<data-dir>/ca.token`.
+    #[arg(long)]
+    pub token_file: Option<PathBuf>,
+
+    /// Tokio worker threads. Defaults to one per CPU core.
+    #[arg(long, env = "CA_WORKER_THREADS", conflicts_with = "single_thread")]
+    pub worker_threads: Option<NonZeroUsize>,
+
+    /// Run everything on one thread (a current-thread runtime). Enough for a
+    /// single user on a small machine.
+    #[arg(long)]
+    pub single_thread: bool,
+
+    /// Shape of the log lines written to stdout. `RUST_LOG` still picks the
+    /// level.
+    #[arg(long, env = "CA_LOG_FORMAT", value_enum, default_value_t)]
+    pub log_format: LogFormat,
+}
+
+/// Log line formats for `--log-format`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum LogFormat {
+    /// Multi-field human-readable lines.
+    #[default]
+    Human,
+    /// Shorter human-readable lines, span fields folded in.
+    Compact,
+    /// One JSON object per line, for log shippers.
+    Json,
+}
+
+impl Config {
+    /// Data directory after applying the `$HOME/.work` fallback; `None`
+    /// when there is no home directory to fall back to.
+    pub fn data_dir(&self) -> Option<PathBuf> {
+        match &self.data_dir {
+            Some(p) => Some(p.clone()),
+            None => work_dir(),
+        }
+    }
+
+    /// Socket path after applying the `<data-dir>/ca.sock` fallback.
+    pub fn socket_path(&self) -> Option<PathBuf> {
+        match &self.socket {
+            Some(p) => Some(p.clone()),
+            #[cfg(unix)]
+            None => self
+                .data_dir()
+                .map(|d| d.join(format!("{}.sock", self.instance_stem("ca")))),
+            #[cfg(windows)]
+            None => Some(PathBuf::from(format!(
+                r"\\.\pipe\{}",
+                self.instance_stem("ca")
+            ))),
+        }
+    }
+
+    /// Token file path after applying the `<data-dir>/ca.token` fallback.
+    pub fn token_path(&self) -> Option<PathBuf> {
+        match &self.token_file {
+            Some(p) => Some(p.clone()),
+            None => self.data_dir().map(|d| d.join("ca.token")),
+        }
+    }
+
+    /// Every setting with fallbacks applied, for `GetConfig`. Holds paths and
+    /// flags only; the auth token itself lives in `AppState`, never here.
+    /// Paths that fall back to an unset `HOME` come out as `null`.
+    pub fn effective(&self) -> Value {
+        json!({
+            "data_dir": self.data_dir(),
+            "socket": self.socket_path(),
+            "instance": self.instance,
+            "max_requests_per_sec": self.max_requests_per_sec,
+            "max_connections": self.max_connections,
+            "require_auth": self.require_auth,
+            "token_file": self.token_path(),
+            "worker_threads": self.worker_threads,
+            "single_thread": self.single_thread,
+            "log_format": self.log_format.to_possible_value().map(|v| v.get_name().to_owned()),
+        })
+    }
+
+    /// `<stem>`, or `<stem>-<instance>` for a named instance.
+    fn instance_stem(&self, stem: &str) -> String {
+        match &self.instance {
+            Some(name) => format!("{stem}-{name}"),
+            None => stem.to_owned(),
+        }
+    }
+}
+
+/// Instance names end up in filenames: keep them to `[A-Za-z0-9_-]`.
+fn parse_instance(raw: &str) -> Result<String, String> {
+    if raw.is_empty() {
+        return Err("instance name must not be empty".to_owned());
+    }
+    if let Some(bad) = raw
+        .chars()
+        .find(|c| !(c.is_ascii_alphanumeric() || *c == '-' || *c == '_'))
+    {
+        return Err(format!(
+            "instance name may only contain letters, digits, '-' and '_' (found {bad:?})"
+        ));
+    }
+    Ok(raw.to_owned())
+}
+
+/// Test-only parsing that ignores the `env =` fallbacks, so a `CA_*`
+/// variable exported in the developer's shell can't change the result.
+#[cfg(test)]
+impl Config {
+    pub fn try_parse_without_env<I, T>(args: I) -> Result<Self, clap::Error>
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<std::ffi::OsString> + Clone,
+    {
+        use clap::{CommandFactory, FromArgMatches};
+
+        let matches = Self::command()
+            .mut_args(|a| a.env(None))
+            .try_get_matches_from(args)?;
+        Self::from_arg_matches(&matches)
+    }
+
+    pub fn parse_without_env<I, T>(args: I) -> Self
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<std::ffi::OsString> + Clone,
+    {
+        Self::try_parse_without_env(args).unwrap_or_else(|e| panic!("{e}"))
+    }
+}
+
+fn work_dir() -> Option<PathBuf> {
+    let home = std::env::var_os("HOME").or_else(|| std::env::var_os("USERPROFILE"))?;
+    Some(PathBuf::from(home).join(".work"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn explicit_socket_flag_is_used_verbatim() {
+        let c = Config::parse_without_env(["ca-daemon", "--socket", "/tmp/x.sock"]);
+        assert_eq!(c.socket_path(), Some(PathBuf::from("/tmp/x.sock")));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn instance_suffixes_default_socket_name() {
+        let c = Config::parse_without_env(["ca-daemon", "--instance", "work-2"]);
+        assert_eq!(
+            c.socket_path(),
+            work_dir().map(|d| d.join("ca-work-2.sock"))
+        );
+        let c = Config::parse_without_env(["ca-daemon"]);
+        assert_eq!(c.socket_path(), work_dir().map(|d| d.join("ca.sock")));
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn instance_suffixes_default_pipe_name() {
+        let c = Config::parse_without_env(["ca-daemon", "--instance", "work-2"]);
+        assert_eq!(c.socket_path(), Some(PathBuf::from(r"\\.\pipe\ca-work-2")));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn data_dir_relocates_derived_paths() {
+        let c =
+            Config::parse_without_env(["ca-daemon", "--data-dir", "/srv/ca", "--instance", "b"]);
+        assert_eq!(c.socket_path(), Some(PathBuf::from("/srv/ca/ca-b.sock")));
+        assert_eq!(c.token_path(), Some(PathBuf::from("/srv/ca/ca.token")));
+
+        let c =
+            Config::parse_without_env(["ca-daemon", "--data-dir", "/srv/ca", "--socket", "/tmp/s"]);
+        assert_eq!(c.socket_path(), Some(PathBuf::from("/tmp/s")));
+    }
+
+    #[test]
+    fn data_dir_defaults_to_work_dir() {
+        let c = Config::parse_without_env(["ca-daemon"]);
+        assert_eq!(c.data_dir(), work_dir());
+    }
+
+    #[test]
+    fn explicit_socket_beats_instance() {
+        let c =
+            Config::parse_without_env(["ca-daemon", "--instance", "a", "--socket", "/tmp/s.sock"]);
+        assert_eq!(c.socket_path(), Some(PathBuf::from("/tmp/s.sock")));
+    }
+
+    #[test]
+    fn instance_names_are_validated() {
+        assert!(Config::try_parse_without_env(["ca-daemon", "--instance", "../etc"]).is_err());
+        assert!(Config::try_parse_without_env(["ca-daemon", "--instance", "a b"]).is_err());
+        assert!(Config::try_parse_without_env(["ca-daemon", "--instance", "dev_1"]).is_ok());
+    }
+
+    #[test]
+    fn rate_limit_defaults_and_overrides() {
+        let c = Config::parse_without_env(["ca-daemon"]);
+        assert_eq!(c.max_requests_per_sec, 200);
+        let c = Config::parse_without_env(["ca-daemon", "--max-requests-per-sec", "0"]);
+        assert_eq!(c.max_requests_per_sec, 0);
+    }
+
+    #[test]
+    fn connection_limit_defaults_and_overrides() {
+        let c = Config::parse_without_env(["ca-daemon"]);
+        assert_eq!(c.max_connections, 256);
+        let c = Config::parse_without_env(["ca-daemon", "--max-connections", "2"]);
+        assert_eq!(c.max_connections, 2);
+    }
+
+    #[test]
+    fn runtime_flags_parse_and_conflict() {
+        let c = Config::parse_without_env(["ca-daemon"]);
+        assert_eq!(c.worker_threads, None);
+        assert!(!c.single_thread);
+        let c = Config::parse_without_env(["ca-daemon", "--worker-threads", "2"]);
+        assert_eq!(c.worker_threads, NonZeroUsize::new(2));
+        assert!(Config::try_parse_without_env(["ca-daemon", "--worker-threads", "0"]).is_err());
+        assert!(
+            Config::try_parse_without_env([
+                "ca-daemon",
+                "--worker-threads",
+                "2",
+                "--single-thread"
+            ])
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn log_format_defaults_to_human() {
+        let c = Config::parse_without_env(["ca-daemon"]);
+        assert_eq!(c.log_format, LogFormat::Human);
+        let c = Config::parse_without_env(["ca-daemon", "--log-format", "json"]);
+        assert_eq!(c.log_format, LogFormat::Json);
+        assert!(Config::try_parse_without_env(["ca-daemon", "--log-format", "xml"]).is_err());
+    }
+
+    #[test]
+    fn effective_config_resolves_fallbacks() {
+        let c = Config::parse_without_env([
+            "ca-daemon",
+            "--socket",
+            "/tmp/s.sock",
+            "--worker-threads",
+            "3",
+            "--log-format",
+            "compact",
+        ]);
+        let e = c.effective();
+        assert_eq!(e["socket"], "/tmp/s.sock");
+        assert_eq!(e["token_file"], json!(c.token_path()));
+        assert_eq!(e["worker_threads"], 3);
+        assert_eq!(e["log_format"], "compact");
+        assert_eq!(e["instance"], Value::Null);
+        assert_eq!(e["max_requests_per_sec"], 200);
+    }
+
+    #[test]
+    fn auth_is_off_by_default() {
+        let c = Config::parse_without_env(["ca-daemon"]);
+        assert!(!c.require_auth);
+        let c =
+            Config::parse_without_env(["ca-daemon", "--require-auth", "--token-file", "/tmp/t"]);
+        assert!(c.require_auth);
+        assert_eq!(c.token_path(), Some(PathBuf::from("/tmp/t")));
+    }
+}
@@ -0,0 +1,162 @@
+//! Bounded log of recent internal warnings, served by the `Diagnostics` RPC.
+//!
+//! Things a client might otherwise only see in the daemon's log: frames that
+//! failed to decode, throttled requests, clients turned away at the
+//! connection limit, accept errors. Old entries fall off the front once the
+//! buffer is full. A [`Run`] folds a stretch of back-to-back warnings from
+//! one source into a single counted entry, so one misbehaving client can't
+//! flush everyone else's.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use ca_lib::{Diagnostic, DiagnosticKind};
+
+/// Entries kept before the oldest is dropped.
+const CAPACITY: usize = 64;
+
+/// Shared ring buffer of [`Diagnostic`]s.
+#[derive(Clone, Default)]
+pub struct Diagnostics {
+    inner: Arc<Mutex<Ring>>,
+}
+
+#[derive(Default)]
+struct Ring {
+    entries: VecDeque<Diagnostic>,
+    /// Sequence number of `entries[0]`; every entry ever recorded gets the
+    /// next one.
+    first_seq: u64,
+}
+
+impl Diagnostics {
+    /// Append a warning, evicting the oldest when full. Returns the entry's
+    /// sequence number for [`Diagnostics::bump`].
+    pub fn record(&self, kind: DiagnosticKind, message: impl Into<String>) -> u64 {
+        let at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs() as i64);
+        let mut ring = self.lock();
+        if ring.entries.len() == CAPACITY {
+            ring.entries.pop_front();
+            ring.first_seq += 1;
+        }
+        ring.entries.push_back(Diagnostic {
+            at,
+            kind,
+            message: message.into(),
+            count: 1,
+        });
+        ring.first_seq + ring.entries.len() as u64 - 1
+    }
+
+    /// Count one more occurrence against entry `seq`. `false` once it has
+    /// been evicted.
+    pub fn bump(&self, seq: u64) -> bool {
+        let mut ring = self.lock();
+        let Some(index) = seq.checked_sub(ring.first_seq) else {
+            return false;
+        };
+        match ring.entries.get_mut(index as usize) {
+            Some(entry) => {
+                entry.count = entry.count.saturating_add(1);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Snapshot, oldest first.
+    pub fn recent(&self) -> Vec<Diagnostic> {
+        self.lock().entries.iter().cloned().collect()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Ring> {
+        // A poisoned buffer is still structurally valid; keep serving.
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// One source's current stretch of a single kind of warning. The first
+/// occurrence records an entry; repeats bump its count until [`Run::end`].
+#[derive(Debug)]
+pub struct Run {
+    kind: DiagnosticKind,
+    seq: Option<u64>,
+}
+
+impl Run {
+    pub fn new(kind: DiagnosticKind) -> Self {
+        Self { kind, seq: None }
+    }
+
+    /// Record one occurrence; `message` is only built for a new entry.
+    pub fn record(&mut self, diagnostics: &Diagnostics, message: impl FnOnce() -> String) {
+        if self.seq.is_some_and(|seq| diagnostics.bump(seq)) {
+            return;
+        }
+        self.seq = Some(diagnostics.record(self.kind, message()));
+    }
+
+    /// The stretch is over; the next occurrence starts a new entry.
+    pub fn end(&mut self) {
+        self.seq = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_the_newest_entries_in_order() {
+        let d = Diagnostics::default();
+        for i in 0..CAPACITY + 3 {
+            d.record(DiagnosticKind::BadRequest, format!("warning {i}"));
+        }
+        let recent = d.recent();
+        assert_eq!(recent.len(), CAPACITY);
+        assert_eq!(recent[0].message, "warning 3");
+        assert_eq!(
+            recent.last().unwrap().message,
+            format!("warning {}", CAPACITY + 2)
+        );
+    }
+
+    #[test]
+    fn a_run_folds_into_one_counted_entry() {
+        let d = Diagnostics::default();
+        let mut run = Run::new(DiagnosticKind::RateLimited);
+        for _ in 0..CAPACITY * 2 {
+            run.record(&d, || "throttled".to_owned());
+        }
+        d.record(DiagnosticKind::Accept, "other");
+        run.end();
+        run.record(&d, || "throttled again".to_owned());
+
+        let recent = d.recent();
+        let counts: Vec<_> = recent.iter().map(|e| (e.kind, e.count)).collect();
+        assert_eq!(
+            counts,
+            [
+                (DiagnosticKind::RateLimited, CAPACITY as u32 * 2),
+                (DiagnosticKind::Accept, 1),
+                (DiagnosticKind::RateLimited, 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn a_run_restarts_once_its_entry_is_evicted() {
+        let d = Diagnostics::default();
+        let mut run = Run::new(DiagnosticKind::BadRequest);
+        run.record(&d, || "first".to_owned());
+        for i in 0..CAPACITY {
+            d.record(DiagnosticKind::Accept, format!("filler {i}"));
+        }
+        run.record(&d, || "second".to_owned());
+        let last = d.recent().pop().unwrap();
+        assert_eq!((last.message.as_str(), last.count), ("second", 1));
+    }
+}
@@ -0,0 +1,266 @@
+//! JSON-RPC 2.0 envelope over the native protocol.
+//!
+//! A frame whose top-level object carries `"jsonrpc"` is treated as a
+//! JSON-RPC call. The method name is the native request's `type` tag and
+//! `params` holds its fields, so `{"jsonrpc":"2.0","id":1,"method":"ping"}`
+//! dispatches exactly like `{"type":"ping"}`. Replies are spec-shaped
+//! result/error objects; calls without an `id` are notifications and get no
+//! reply. Batches are rejected as invalid requests.
+
+use ca_lib::{ErrorCode, RpcRequest, RpcResponse};
+use serde_json::{Map, Value, json};
+
+pub const PARSE_ERROR: i64 = -32700;
+pub const INVALID_REQUEST: i64 = -32600;
+pub const METHOD_NOT_FOUND: i64 = -32601;
+pub const INVALID_PARAMS: i64 = -32602;
+/// Start of the implementation-defined server error range; daemon
+/// [`ErrorCode`]s count down from here.
+const SERVER_ERROR: i64 = -32000;
+
+/// A well-formed call, ready for dispatch.
+#[derive(Debug)]
+pub struct Call {
+    /// `None` for notifications.
+    pub id: Option<Value>,
+    pub request: RpcRequest,
+}
+
+/// A call rejected before dispatch.
+#[derive(Debug)]
+pub struct Failure {
+    /// `None` when the offending call was a notification (no reply is sent).
+    pub id: Option<Value>,
+    pub code: i64,
+    pub message: String,
+}
+
+/// Cheap sniff for frames that look like JSON-RPC but failed to parse, so the
+/// parse error can be answered in JSON-RPC shape.
+pub fn looks_like_jsonrpc(frame: &[u8]) -> bool {
+    frame.windows(9).any(|w| w == b"\"jsonrpc\"")
+}
+
+/// Whether a parsed frame is addressed to the JSON-RPC layer.
+pub fn is_jsonrpc(value: &Value) -> bool {
+    value.is_array() || value.get("jsonrpc").is_some()
+}
+
+/// Turn a JSON-RPC request object into a native request.
+pub fn parse_call(value: Value) -> Result<Call, Failure> {
+    let Value::Object(mut obj) = value else {
+        return Err(invalid_request("batch requests are not supported"));
+    };
+    if obj.get("jsonrpc").and_then(Value::as_str) != Some("2.0") {
+        return Err(invalid_request("\"jsonrpc\" must be \"2.0\""));
+    }
+    let id = match obj.remove("id") {
+        None => None,
+        Some(id @ (Value::Null | Value::Number(_) | Value::String(_))) => Some(id),
+        Some(_) => return Err(invalid_request("\"id\" must be a string, number, or null")),
+    };
+    let Some(Value::String(method)) = obj.remove("method") else {
+        return Err(Failure {
+            id: Some(id.unwrap_or(Value::Null)),
+            ..invalid_request("\"method\" must be a string")
+        });
+    };
+    if !RpcRequest::KINDS.contains(&method.as_str()) {
+        return Err(Failure {
+            id,
+            code: METHOD_NOT_FOUND,
+            message: format!("method not found: {method}"),
+        });
+    }
+    let mut fields = match obj.remove("params") {
+        None | Some(Value::Null) => Map::new(),
+        Some(Value::Object(map)) => map,
+        Some(_) => {
+            return Err(Failure {
+                id,
+                code: INVALID_PARAMS,
+                message: "params must be an object".to_owned(),
+            });
+        }
+    };
+    if fields.contains_key("type") {
+        return Err(Failure {
+            id,
+            code: INVALID_PARAMS,
+            message: "params must not carry \"type\"".to_owned(),
+        });
+    }
+    fields.insert("type".to_owned(), Value::String(method));
+
+    match serde_json::from_value::<RpcRequest>(Value::Object(fields)) {
+        Ok(request) => Ok(Call { id, request }),
+        Err(e) => Err(Failure {
+            id,
+            code: INVALID_PARAMS,
+            message: format!("invalid params: {e}"),
+        }),
+    }
+}
+
+/// Render a dispatch outcome. Native errors map into the server error range
+/// with the native code in `data`; anything else becomes `result`.
+pub fn encode_response(id: &Value, response: &RpcResponse) -> Value {
+    match response {
+        RpcResponse::Error { code, message } => json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "error": {
+                "code": server_error_code(*code),
+                "message": message,
+                "data": { "code": code },
+            },
+        }),
+        other => json!({ "jsonrpc": "2.0", "id": id, "result": other }),
+    }
+}
+
+/// Render a daemon push as a notification: the `type` tag becomes the
+/// method and any other fields become `params`.
+pub fn encode_notification(push: &RpcResponse) -> serde_json::Result<Value> {
+    // Responses are internally tagged, so this is always an object.
+    let mut fields = match serde_json::to_value(push)? {
+        Value::Object(fields) => fields,
+        _ => Map::new(),
+    };
+    let method = fields.remove("type").unwrap_or(Value::Null);
+    let mut note = json!({ "jsonrpc": "2.0", "method": method });
+    if !fields.is_empty() {
+        note["params"] = Value::Object(fields);
+    }
+    Ok(note)
+}
+
+/// Render a pre-dispatch failure.
+pub fn encode_failure(id: &Value, code: i64, message: &str) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "error": { "code": code, "message": message },
+    })
+}
+
+fn server_error_code(code: ErrorCode) -> i64 {
+    match code {
+        ErrorCode::BadRequest => INVALID_REQUEST,
+        ErrorCode::NotImplemented => SERVER_ERROR - 1,
+        ErrorCode::RateLimited => SERVER_ERROR - 2,
+        ErrorCode::Unauthorized => SERVER_ERROR - 3,
+        ErrorCode::Busy => SERVER_ERROR - 4,
+    }
+}
+
+fn invalid_request(message: &str) -> Failure {
+    Failure {
+        id: Some(Value::Null),
+        code: INVALID_REQUEST,
+        message: message.to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn valid_call_maps_to_native_request() {
+        let call = parse_call(json!({"jsonrpc": "2.0", "id": 7, "method": "ping"})).unwrap();
+        assert_eq!(call.id, Some(json!(7)));
+        assert_eq!(call.request, RpcRequest::Ping);
+
+        let call = parse_call(json!({
+            "jsonrpc": "2.0",
+            "id": "a",
+            "method": "task_get",
+            "params": {"task_id": "M1-T4"},
+        }))
+        .unwrap();
+        assert_eq!(
+            call.request,
+            RpcRequest::TaskGet {
+                task_id: "M1-T4".to_owned()
+            }
+        );
+    }
+
+    #[test]
+    fn notification_has_no_id() {
+        let call = parse_call(json!({"jsonrpc": "2.0", "method": "ping"})).unwrap();
+        assert!(call.id.is_none());
+    }
+
+    #[test]
+    fn unknown_method_is_method_not_found() {
+        let f =
+            parse_call(json!({"jsonrpc": "2.0", "id": 1, "method": "list_sessions"})).unwrap_err();
+        assert_eq!(f.code, METHOD_NOT_FOUND);
+        assert_eq!(f.id, Some(json!(1)));
+    }
+
+    #[test]
+    fn missing_field_is_invalid_params() {
+        let f = parse_call(json!({"jsonrpc": "2.0", "id": 1, "method": "task_get"})).unwrap_err();
+        assert_eq!(f.code, INVALID_PARAMS);
+    }
+
+    #[test]
+    fn bad_nested_enum_value_is_invalid_params() {
+        let f = parse_call(json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": "set_framing",
+            "params": {"framing": "bogus"},
+        }))
+        .unwrap_err();
+        assert_eq!(f.code, INVALID_PARAMS);
+        assert!(f.message.contains("bogus"), "message: {}", f.message);
+    }
+
+    #[test]
+    fn wrong_version_and_batches_are_invalid_requests() {
+        let f = parse_call(json!({"jsonrpc": "1.0", "id": 1, "method": "ping"})).unwrap_err();
+        assert_eq!(f.code, INVALID_REQUEST);
+        assert_eq!(f.id, Some(Value::Null));
+        let f = parse_call(json!([{"jsonrpc": "2.0", "id": 1, "method": "ping"}])).unwrap_err();
+        assert_eq!(f.code, INVALID_REQUEST);
+    }
+
+    #[test]
+    fn encode_response_splits_result_and_error() {
+        let ok = encode_response(&json!(1), &RpcResponse::Pong { uptime_s: 5 });
+        assert_eq!(ok["result"]["uptime_s"], 5);
+        assert!(ok.get("error").is_none());
+
+        let err = encode_response(
+            &json!(2),
+            &RpcResponse::Error {
+                code: ErrorCode::RateLimited,
+                message: "slow down".to_owned(),
+            },
+        );
+        assert_eq!(err["error"]["code"], SERVER_ERROR - 2);
+        assert_eq!(err["error"]["data"]["code"], "rate_limited");
+        assert!(err.get("result").is_none());
+    }
+
+    #[test]
+    fn pushes_become_notifications() {
+        let note = encode_notification(&RpcResponse::ShuttingDown).unwrap();
+        assert_eq!(note, json!({"jsonrpc": "2.0", "method": "shutting_down"}));
+
+        let note = encode_notification(&RpcResponse::Pong { uptime_s: 3 }).unwrap();
+        assert_eq!(note["method"], "pong");
+        assert_eq!(note["params"], json!({"uptime_s": 3}));
+        assert!(note.get("id").is_none());
+    }
+
+    #[test]
+    fn sniff_matches_only_jsonrpc_frames() {
+        assert!(looks_like_jsonrpc(br#"{"jsonrpc":"2.0","method":"#));
+        assert!(!looks_like_jsonrpc(br#"{"type":"ping""#));
+    }
+}
@@ -1,39 +1,66 @@
 //! ca-daemon — local orchestrator daemon for claude_admin v1.
 //!
-//! Entry point. Sets up tracing, resolves the socket path, hands off to
-//! the socket module which owns the lifecycle.
+//! Entry point. Parses the config, sets up tracing, builds the runtime, hands
+//! off to the socket module which owns the lifecycle.
 
-use std::path::PathBuf;
 use std::process::ExitCode;
 
+use clap::Parser;
+use tokio::runtime::{Builder, Runtime};
 use tracing_subscriber::EnvFilter;
 
+mod auth;
+mod codec;
+mod config;
+mod diagnostics;
+mod jsonrpc;
+mod ratelimit;
+mod registry;
 mod rpc;
 mod socket;
+mod transport;
 
-/// Resolve the socket path from the `CA_SOCKET_PATH` env var (test override)
-/// or fall back to `$HOME/.work/ca.sock`.
-fn resolve_socket_path() -> PathBuf {
-    if let Some(p) = std::env::var_os("CA_SOCKET_PATH") {
-        return PathBuf::from(p);
-    }
-    let home = std::env::var_os("HOME").expect("HOME env var must be set");
-    PathBuf::from(home).join(".work").join("ca.sock")
-}
+use config::{Config, LogFormat};
 
-fn init_tracing() {
+fn init_tracing(format: LogFormat) {
     let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
-    tracing_subscriber::fmt()
+    let builder = tracing_subscriber::fmt()
         .with_env_filter(filter)
-        .with_target(false)
-        .init();
+        .with_target(false);
+    match format {
+        LogFormat::Human => builder.init(),
+        LogFormat::Compact => builder.compact().init(),
+        LogFormat::Json => builder.json().init(),
+    }
 }
 
-#[tokio::main]
-async fn main() -> ExitCode {
-    init_tracing();
-    let path = resolve_socket_path();
-    match socket::serve(path).await {
+/// Multi-thread by default (`--worker-threads` caps the pool);
+/// current-thread with `--single-thread`.
+fn build_runtime(config: &Config) -> std::io::Result<Runtime> {
+    let mut builder = if config.single_thread {
+        Builder::new_current_thread()
+    } else {
+        let mut builder = Builder::new_multi_thread();
+        if let Some(n) = config.worker_threads {
+            builder.worker_threads(n.get());
+        }
+        builder
+    };
+    builder.enable_all().build()
+}
+
+fn main() -> ExitCode {
+    let config = Config::parse();
+    init_tracing(config.log_format);
+    let runtime = match build_runtime(&config) {
+        Ok(rt) => rt,
+        Err(e) => {
+            tracing::error!(error = %e, "building tokio runtime");
+            eprintln!("ca-daemon: building runtime: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    match runtime.block_on(socket::serve(config)) {
         Ok(()) => ExitCode::SUCCESS,
         Err(e) => {
             tracing::error!(error = ?e, "daemon exited with error");
@@ -43,3 +70,20 @@ async fn main() -> ExitCode {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn runtime_honours_thread_flags() {
+        let config = Config::parse_without_env(["ca-daemon", "--worker-threads", "2"]);
+        let rt = build_runtime(&config).unwrap();
+        assert_eq!(rt.metrics().num_workers(), 2);
+
+        let config = Config::parse_without_env(["ca-daemon", "--single-thread"]);
+        let rt = build_runtime(&config).unwrap();
+        assert_eq!(rt.metrics().num_workers(), 1);
+        assert_eq!(rt.block_on(async { 1 + 1 }), 2);
+    }
+}
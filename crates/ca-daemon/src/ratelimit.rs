@@ -0,0 +1,83 @@
+//! Per-connection token bucket.
+//!
+//! Each request takes one token; tokens refill continuously at the configured
+//! rate up to a one-second burst. Time is passed in by the caller so the
+//! bucket stays deterministic under test.
+
+use std::time::Instant;
+
+/// Token bucket sized to `per_sec` tokens with a matching refill rate.
+#[derive(Debug)]
+pub struct TokenBucket {
+    capacity: f64,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    /// A full bucket allowing `per_sec` requests per second.
+    pub fn new(per_sec: u32, now: Instant) -> Self {
+        let capacity = f64::from(per_sec);
+        Self {
+            capacity,
+            tokens: capacity,
+            last_refill: now,
+        }
+    }
+
+    /// Take one token if available. Returns `false` when the caller is over
+    /// budget.
+    pub fn try_acquire(&mut self, now: Instant) -> bool {
+        let elapsed = now
+            .saturating_duration_since(self.last_refill)
+            .as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.capacity).min(self.capacity);
+        self.last_refill = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn burst_up_to_capacity_then_throttles() {
+        let t0 = Instant::now();
+        let mut b = TokenBucket::new(3, t0);
+        assert!(b.try_acquire(t0));
+        assert!(b.try_acquire(t0));
+        assert!(b.try_acquire(t0));
+        assert!(!b.try_acquire(t0));
+    }
+
+    #[test]
+    fn refills_over_time() {
+        let t0 = Instant::now();
+        let mut b = TokenBucket::new(2, t0);
+        assert!(b.try_acquire(t0));
+        assert!(b.try_acquire(t0));
+        assert!(!b.try_acquire(t0));
+        // Half a second at 2/s refills exactly one token.
+        let t1 = t0 + Duration::from_millis(500);
+        assert!(b.try_acquire(t1));
+        assert!(!b.try_acquire(t1));
+    }
+
+    #[test]
+    fn refill_is_capped_at_capacity() {
+        let t0 = Instant::now();
+        let mut b = TokenBucket::new(2, t0);
+        let later = t0 + Duration::from_secs(60);
+        assert!(b.try_acquire(later));
+        assert!(b.try_acquire(later));
+        assert!(!b.try_acquire(later));
+    }
+}
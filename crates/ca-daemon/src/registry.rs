@@ -0,0 +1,156 @@
+//! Registry of live connections.
+//!
+//! Every accepted connection registers an outbound channel here; its writer
+//! half drains that channel. Replies from the connection's own reader and
+//! daemon-initiated pushes (shutdown notices, targeted events) travel
+//! the same path, so no handler needs its own broadcast receiver.
+//! Unregistering drops the registry's sender; once the connection's own
+//! sender is gone too, the writer drains what is queued and stops.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use ca_lib::RpcResponse;
+use tokio::sync::mpsc;
+use tracing::debug;
+
+use crate::rpc::Outbound;
+
+/// Outbound messages buffered per connection before pushes start dropping.
+const OUTBOUND_CAPACITY: usize = 32;
+
+/// Daemon-unique connection id, assigned in accept order starting at 1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ConnId(pub u64);
+
+impl fmt::Display for ConnId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// Shared map of connection id → outbound channel.
+#[derive(Clone, Default)]
+pub struct Connections {
+    senders: Arc<Mutex<HashMap<ConnId, mpsc::Sender<Outbound>>>>,
+    next_id: Arc<AtomicU64>,
+}
+
+impl Connections {
+    /// Allocate an id and an outbound channel for a new connection. The
+    /// returned sender is for the connection's own replies; the registry
+    /// keeps a clone for pushes.
+    pub fn register(&self) -> (ConnId, mpsc::Sender<Outbound>, mpsc::Receiver<Outbound>) {
+        let id = ConnId(self.next_id.fetch_add(1, Ordering::Relaxed) + 1);
+        let (tx, rx) = mpsc::channel(OUTBOUND_CAPACITY);
+        self.lock().insert(id, tx.clone());
+        (id, tx, rx)
+    }
+
+    /// Forget a connection. Idempotent.
+    pub fn unregister(&self, id: ConnId) {
+        self.lock().remove(&id);
+    }
+
+    /// Push `msg` to one connection; `false` if it is gone or its queue is
+    /// full.
+    // No per-connection events exist yet; the first subscription uses this.
+    #[allow(dead_code)]
+    pub fn send_to(&self, id: ConnId, msg: &RpcResponse) -> bool {
+        let Some(tx) = self.lock().get(&id).cloned() else {
+            return false;
+        };
+        match tx.try_send(Outbound::Push(msg.clone())) {
+            Ok(()) => true,
+            Err(e) => {
+                debug!(conn_id = %id, error = %e, "push dropped");
+                false
+            }
+        }
+    }
+
+    /// Push `msg` to every connection; returns how many accepted it.
+    pub fn broadcast(&self, msg: &RpcResponse) -> usize {
+        let senders: Vec<_> = self
+            .lock()
+            .iter()
+            .map(|(id, tx)| (*id, tx.clone()))
+            .collect();
+        let mut delivered = 0;
+        for (id, tx) in senders {
+            match tx.try_send(Outbound::Push(msg.clone())) {
+                Ok(()) => delivered += 1,
+                Err(e) => debug!(conn_id = %id, error = %e, "push dropped"),
+            }
+        }
+        delivered
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<ConnId, mpsc::Sender<Outbound>>> {
+        // A poisoned map is still structurally valid; keep serving.
+        self.senders.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ids_are_monotonic_from_one() {
+        let c = Connections::default();
+        let (a, _, _) = c.register();
+        let (b, _, _) = c.register();
+        assert_eq!(a, ConnId(1));
+        assert_eq!(b, ConnId(2));
+    }
+
+    #[test]
+    fn broadcast_reaches_only_registered_connections() {
+        let c = Connections::default();
+        let (_a, _txa, mut rxa) = c.register();
+        let (b, _txb, mut rxb) = c.register();
+        c.unregister(b);
+
+        assert_eq!(c.broadcast(&RpcResponse::ShuttingDown), 1);
+        assert_eq!(
+            rxa.try_recv().unwrap(),
+            Outbound::Push(RpcResponse::ShuttingDown)
+        );
+        assert!(rxb.try_recv().is_err());
+    }
+
+    #[test]
+    fn send_to_reaches_only_the_target() {
+        let c = Connections::default();
+        let (a, _txa, mut rxa) = c.register();
+        let (b, _txb, mut rxb) = c.register();
+
+        assert!(c.send_to(a, &RpcResponse::ShuttingDown));
+        assert_eq!(
+            rxa.try_recv().unwrap(),
+            Outbound::Push(RpcResponse::ShuttingDown)
+        );
+        assert!(rxb.try_recv().is_err());
+
+        c.unregister(b);
+        assert!(!c.send_to(b, &RpcResponse::ShuttingDown));
+        assert!(!c.send_to(ConnId(99), &RpcResponse::ShuttingDown));
+    }
+
+    #[test]
+    fn reply_sender_outlives_unregister() {
+        // The connection's own sender keeps working after the registry drops
+        // its clone, so queued replies still drain on the way out.
+        let c = Connections::default();
+        let (id, tx, mut rx) = c.register();
+        c.unregister(id);
+        let reply = Outbound::Native(RpcResponse::Authenticated);
+        tx.try_send(reply.clone()).unwrap();
+        drop(tx);
+        assert_eq!(rx.try_recv().unwrap(), reply);
+        assert!(rx.try_recv().is_err());
+    }
+}
@@ -1,102 +1,489 @@
-//! RPC dispatch over the UDS.
+//! RPC dispatch over the daemon socket.
 //!
-//! Newline-delimited JSON. Each line is an `RpcRequest`; the daemon writes one
-//! `RpcResponse` line per request. Connections survive multiple round-trips
-//! until the client closes them (EOF). Malformed JSON or unknown variants
-//! produce an `RpcResponse::Error` and the connection stays open for the next
-//! request — only socket-level errors close it.
+//! Newline-delimited JSON by default; a connection may switch to
+//! length-prefixed frames with `SetFraming` (see [`crate::codec`]). Each frame
+//! is an `RpcRequest`; the daemon writes one `RpcResponse` frame per request.
+//! Frames may instead be JSON-RPC 2.0 calls (see [`crate::jsonrpc`]); the two
+//! protocols can be mixed on one connection.
+//!
+//! Connections survive multiple round-trips until the client closes them
+//! (EOF). Malformed JSON or unknown variants produce an `RpcResponse::Error`
+//! and the connection stays open for the next request — only socket-level
+//! errors close it. Requests over the per-connection rate limit get
+//! `ErrorCode::RateLimited` without being dispatched. When auth is required,
+//! anything other than a correct `Auth` as the first request gets
+//! `ErrorCode::Unauthorized` and the connection is closed.
+//!
+//! Each connection runs a read loop and a write loop joined by the outbound
+//! channel from [`crate::registry`], so daemon pushes interleave with replies
+//! without sharing the socket's write half. Pushes follow the protocol of the
+//! client's latest frame: a native message, or a JSON-RPC notification.
 
 use std::sync::Arc;
-use std::time::Instant;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use ca_lib::{DiagnosticKind, ErrorCode, Framing, RpcRequest, RpcResponse};
+use serde_json::Value;
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::sync::mpsc;
+use tracing::{Instrument, debug, info_span, warn};
 
-use ca_lib::{RpcRequest, RpcResponse};
-use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
-use tokio::net::UnixStream;
-use tracing::{debug, warn};
+use crate::auth;
+use crate::codec;
+use crate::config::Config;
+use crate::diagnostics::{Diagnostics, Run};
+use crate::jsonrpc;
+use crate::ratelimit::TokenBucket;
+use crate::registry::{ConnId, Connections};
 
 /// Wire-protocol version. Increment when the request/response shape changes
 /// in a breaking way.
 pub const PROTOCOL_VERSION: u32 = 1;
 
+/// How long [`reject_busy`] waits to deliver its reply.
+const REJECT_TIMEOUT: Duration = Duration::from_secs(1);
+
 /// Per-daemon state surfaced into RPC handlers.
 #[derive(Clone)]
 pub struct AppState {
     pub started_at: Instant,
+    /// Wall-clock start time in unix seconds, for `Version`.
+    pub started_at_unix: i64,
+    pub config: Config,
+    /// Loaded token when `--require-auth` is set.
+    pub auth_token: Option<String>,
+    /// Live connections, for daemon-initiated pushes.
+    pub connections: Connections,
+    /// Recent internal warnings, for `Diagnostics`.
+    pub diagnostics: Diagnostics,
+}
+
+/// Read framed requests from the stream and write one response frame per
+/// request. Returns when the client closes the connection, or after the
+/// daemon pushes `ShuttingDown`. Everything logged for the connection sits
+/// in a `conn{id=..}` span.
+pub async fn handle_connection<S>(stream: S, state: Arc<AppState>)
+where
+    S: AsyncRead + AsyncWrite,
+{
+    let (id, tx, rx) = state.connections.register();
+    let span = info_span!("conn", id = %id);
+    serve_connection(stream, id, tx, rx, state)
+        .instrument(span)
+        .await;
+}
+
+async fn serve_connection<S>(
+    stream: S,
+    id: ConnId,
+    tx: mpsc::Sender<Outbound>,
+    rx: mpsc::Receiver<Outbound>,
+    state: Arc<AppState>,
+) where
+    S: AsyncRead + AsyncWrite,
+{
+    debug!("connection opened");
+    let (read_half, write_half) = tokio::io::split(stream);
+    // Set by the reader from each frame's envelope; read by the writer to
+    // shape pushes.
+    let jsonrpc = AtomicBool::new(false);
+
+    let writer = write_loop(write_half, rx, &jsonrpc);
+    tokio::pin!(writer);
+    tokio::select! {
+        () = read_loop(read_half, tx, &state, &jsonrpc) => {
+            // Drop the registry's sender so the writer ends once the
+            // replies already queued are flushed.
+            state.connections.unregister(id);
+            writer.await;
+        }
+        () = &mut writer => {}
+    }
+    state.connections.unregister(id);
+    debug!("connection closed");
+}
+
+/// Turn away a connection over the daemon's connection limit: one native
+/// `busy` error, then close. Bounded so a client that never reads can't
+/// pin the task.
+pub async fn reject_busy<S>(mut stream: S, limit: u32)
+where
+    S: AsyncWrite + Unpin,
+{
+    let reply = Outbound::Native(RpcResponse::Error {
+        code: ErrorCode::Busy,
+        message: format!("busy: daemon is serving its maximum of {limit} connections"),
+    });
+    let Ok(payload) = reply.to_vec(false) else {
+        return;
+    };
+    let frame = codec::encode_frame(Framing::Line, &payload);
+    let send = async {
+        stream.write_all(&frame).await?;
+        stream.shutdown().await
+    };
+    match tokio::time::timeout(REJECT_TIMEOUT, send).await {
+        Ok(Ok(())) => {}
+        Ok(Err(e)) => debug!(error = %e, "busy reply not delivered"),
+        Err(_) => debug!("busy reply timed out"),
+    }
 }
 
-/// Read newline-delimited requests from the stream and write one response
-/// line per request. Returns when the client closes the connection.
-pub async fn handle_connection(stream: UnixStream, state: Arc<AppState>) {
-    let (read_half, mut write_half) = stream.into_split();
+/// Parse and dispatch requests, queueing each reply on `tx`.
+async fn read_loop<R>(
+    read_half: R,
+    tx: mpsc::Sender<Outbound>,
+    state: &AppState,
+    jsonrpc: &AtomicBool,
+) where
+    R: AsyncRead + Unpin,
+{
     let mut reader = BufReader::new(read_half);
-    let mut line = String::new();
+    let mut frame = Vec::new();
+    let mut framing = Framing::default();
+    let mut bucket = match state.config.max_requests_per_sec {
+        0 => None,
+        n => Some(TokenBucket::new(n, Instant::now())),
+    };
+    let mut authed = state.auth_token.is_none();
+    // Back-to-back warnings from this connection share one diagnostic.
+    let mut bad_run = Run::new(DiagnosticKind::BadRequest);
+    let mut throttled_run = Run::new(DiagnosticKind::RateLimited);
 
     loop {
-        line.clear();
-        match reader.read_line(&mut line).await {
-            Ok(0) => break, // EOF
-            Ok(_) => {}
+        match codec::read_frame(&mut reader, framing, &mut frame).await {
+            Ok(false) => break, // EOF
+            Ok(true) => {}
+            Err(e) if is_disconnect(&e) => {
+                debug!(error = %e, "client disconnected mid-read");
+                break;
+            }
             Err(e) => {
                 debug!(error = %e, "rpc read error");
                 break;
             }
         }
-        let trimmed = line.trim_end_matches(['\n', '\r']);
-        let response = match serde_json::from_str::<RpcRequest>(trimmed) {
-            Ok(req) => dispatch(req, &state),
-            Err(e) => RpcResponse::Error {
-                message: format!("parse error: {e}"),
-            },
+        // Decode before throttling so even a rate-limit reply goes out in the
+        // caller's envelope.
+        let (envelope, decoded) = decode(&frame);
+        jsonrpc.store(matches!(envelope, Envelope::JsonRpc(_)), Ordering::Relaxed);
+        match &decoded {
+            Ok(req) => debug!(request = req.kind(), "request"),
+            Err(_) => debug!("undecodable request"),
+        }
+        let throttled = bucket
+            .as_mut()
+            .is_some_and(|b| !b.try_acquire(Instant::now()));
+        // A throttled frame counts only as throttled, whatever it held.
+        if throttled {
+            throttled_run.record(&state.diagnostics, || {
+                format!(
+                    "requests over the {} requests/s limit",
+                    state.config.max_requests_per_sec
+                )
+            });
+        } else {
+            throttled_run.end();
+            match &decoded {
+                Ok(_) => bad_run.end(),
+                Err(reply) => bad_run.record(&state.diagnostics, || {
+                    reply
+                        .as_ref()
+                        .and_then(Outbound::error_message)
+                        .unwrap_or("invalid JSON-RPC notification")
+                        .to_owned()
+                }),
+            }
+        }
+        let mut reframe = None;
+        let reply = if throttled {
+            envelope.wrap(RpcResponse::Error {
+                code: ErrorCode::RateLimited,
+                message: format!(
+                    "rate limited: over {} requests/s",
+                    state.config.max_requests_per_sec
+                ),
+            })
+        } else {
+            match decoded {
+                Ok(req) if !authorize(&req, state, &mut authed) => {
+                    envelope.wrap(RpcResponse::Error {
+                        code: ErrorCode::Unauthorized,
+                        message: "unauthorized: open the connection with a valid auth request"
+                            .to_owned(),
+                    })
+                }
+                Ok(req) => {
+                    let response = dispatch(req, state);
+                    if let RpcResponse::FramingSet { framing: next } = response {
+                        reframe = Some(next);
+                    }
+                    envelope.wrap(response)
+                }
+                Err(reply) => reply,
+            }
         };
 
-        let mut out = match serde_json::to_string(&response) {
-            Ok(s) => s,
+        // The client's next frame already uses the new framing. A
+        // notification gets no acknowledgement, so tell the writer directly.
+        let reply = match (reply, reframe) {
+            (None, Some(next)) => Some(Outbound::Reframe(next)),
+            (reply, _) => reply,
+        };
+        if let Some(next) = reframe {
+            framing = next;
+        }
+        if let Some(reply) = reply {
+            if tx.send(reply).await.is_err() {
+                break; // writer gone
+            }
+        }
+        if !authed {
+            debug!("closing unauthenticated connection");
+            break;
+        }
+    }
+}
+
+/// Write queued replies and pushes until every sender is dropped, a write
+/// fails, or a `ShuttingDown` notice has gone out. Pushes go out as JSON-RPC
+/// notifications while `jsonrpc` is set.
+async fn write_loop<W>(mut write_half: W, mut rx: mpsc::Receiver<Outbound>, jsonrpc: &AtomicBool)
+where
+    W: AsyncWrite + Unpin,
+{
+    let mut framing = Framing::default();
+
+    while let Some(outbound) = rx.recv().await {
+        if let Outbound::Reframe(next) = outbound {
+            debug!(framing = ?next, "switching framing");
+            framing = next;
+            continue;
+        }
+        let payload = match outbound.to_vec(jsonrpc.load(Ordering::Relaxed)) {
+            Ok(p) => p,
             Err(e) => {
                 warn!(error = %e, "serializing response");
                 break;
             }
         };
-        out.push('\n');
-        if write_half.write_all(out.as_bytes()).await.is_err() {
-            break;
+        let out = codec::encode_frame(framing, &payload);
+        let written = match write_half.write_all(&out).await {
+            Ok(()) => write_half.flush().await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = written {
+            // Stop at the first failure: dropping the receiver makes the
+            // reader's next send fail, and the caller unregisters us, so
+            // pushes stop too.
+            if is_disconnect(&e) {
+                debug!(error = %e, "client disconnected mid-write");
+            } else {
+                warn!(error = %e, "rpc write error");
+            }
+            return;
         }
-        if write_half.flush().await.is_err() {
-            break;
+        match outbound.response() {
+            Some(RpcResponse::FramingSet { framing: next }) => {
+                debug!(framing = ?next, "switching framing");
+                framing = *next;
+            }
+            Some(RpcResponse::ShuttingDown) => break,
+            _ => {}
         }
     }
 
     let _ = write_half.shutdown().await;
 }
 
+/// Errors that mean the peer went away rather than anything wrong on our
+/// side.
+fn is_disconnect(e: &std::io::Error) -> bool {
+    use std::io::ErrorKind::{BrokenPipe, ConnectionAborted, ConnectionReset, NotConnected};
+    matches!(
+        e.kind(),
+        BrokenPipe | ConnectionReset | ConnectionAborted | NotConnected
+    )
+}
+
+/// One message queued for a connection's writer.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Outbound {
+    /// Native-protocol reply.
+    Native(RpcResponse),
+    /// Daemon-initiated message, shaped by the writer to match the client.
+    Push(RpcResponse),
+    /// Dispatch outcome for a JSON-RPC call.
+    JsonRpc { id: Value, response: RpcResponse },
+    /// JSON-RPC call rejected before dispatch.
+    JsonRpcFailure {
+        id: Value,
+        code: i64,
+        message: String,
+    },
+    /// Switch the writer's framing without sending anything; queued for a
+    /// `set_framing` notification, which has no reply to switch after.
+    Reframe(Framing),
+}
+
+impl Outbound {
+    /// The native response inside, if this message went through dispatch.
+    fn response(&self) -> Option<&RpcResponse> {
+        match self {
+            Self::Native(r) | Self::Push(r) | Self::JsonRpc { response: r, .. } => Some(r),
+            Self::JsonRpcFailure { .. } | Self::Reframe(_) => None,
+        }
+    }
+
+    /// Human-readable reason, if this message is an error.
+    fn error_message(&self) -> Option<&str> {
+        match self {
+            Self::JsonRpcFailure { message, .. } => Some(message),
+            other => match other.response() {
+                Some(RpcResponse::Error { message, .. }) => Some(message),
+                _ => None,
+            },
+        }
+    }
+
+    /// Wire bytes; `jsonrpc` picks the shape of a push.
+    fn to_vec(&self, jsonrpc: bool) -> serde_json::Result<Vec<u8>> {
+        match self {
+            Self::Native(r) => serde_json::to_vec(r),
+            Self::Push(r) if jsonrpc => serde_json::to_vec(&jsonrpc::encode_notification(r)?),
+            Self::Push(r) => serde_json::to_vec(r),
+            Self::JsonRpc { id, response } => {
+                serde_json::to_vec(&jsonrpc::encode_response(id, response))
+            }
+            Self::JsonRpcFailure { id, code, message } => {
+                serde_json::to_vec(&jsonrpc::encode_failure(id, *code, message))
+            }
+            // Consumed by `write_loop`; never written.
+            Self::Reframe(_) => Ok(Vec::new()),
+        }
+    }
+}
+
+/// Which protocol a request arrived in, and so how to shape its reply.
+enum Envelope {
+    Native,
+    /// JSON-RPC call; `None` id marks a notification that gets no reply.
+    JsonRpc(Option<Value>),
+}
+
+impl Envelope {
+    fn wrap(self, response: RpcResponse) -> Option<Outbound> {
+        match self {
+            Self::Native => Some(Outbound::Native(response)),
+            Self::JsonRpc(Some(id)) => Some(Outbound::JsonRpc { id, response }),
+            Self::JsonRpc(None) => None,
+        }
+    }
+}
+
+/// Decode one frame in either protocol. `Err` carries the reply for a frame
+/// that cannot be dispatched (`None` when the caller expects no reply).
+fn decode(frame: &[u8]) -> (Envelope, Result<RpcRequest, Option<Outbound>>) {
+    let value: Value = match serde_json::from_slice(frame) {
+        Ok(v) => v,
+        Err(e) if jsonrpc::looks_like_jsonrpc(frame) => {
+            let failure = Outbound::JsonRpcFailure {
+                id: Value::Null,
+                code: jsonrpc::PARSE_ERROR,
+                message: format!("parse error: {e}"),
+            };
+            return (Envelope::JsonRpc(None), Err(Some(failure)));
+        }
+        Err(e) => return (Envelope::Native, Err(Some(bad_request(&e)))),
+    };
+    if !jsonrpc::is_jsonrpc(&value) {
+        let decoded = serde_json::from_value(value).map_err(|e| Some(bad_request(&e)));
+        return (Envelope::Native, decoded);
+    }
+    match jsonrpc::parse_call(value) {
+        Ok(call) => (Envelope::JsonRpc(call.id), Ok(call.request)),
+        Err(f) => {
+            let reply = f.id.clone().map(|id| Outbound::JsonRpcFailure {
+                id,
+                code: f.code,
+                message: f.message,
+            });
+            (Envelope::JsonRpc(f.id), Err(reply))
+        }
+    }
+}
+
+fn bad_request(e: &serde_json::Error) -> Outbound {
+    Outbound::Native(RpcResponse::Error {
+        code: ErrorCode::BadRequest,
+        message: format!("parse error: {e}"),
+    })
+}
+
+/// Gate a request on the connection's auth status. An `Auth` request
+/// (re)checks the token; anything else passes only once authenticated.
+fn authorize(req: &RpcRequest, state: &AppState, authed: &mut bool) -> bool {
+    if let RpcRequest::Auth { token } = req {
+        *authed = state
+            .auth_token
+            .as_deref()
+            .is_none_or(|expected| auth::tokens_match(expected, token));
+    }
+    *authed
+}
+
 /// Pure RPC dispatch — kept side-effect-free for unit testing.
 fn dispatch(req: RpcRequest, state: &AppState) -> RpcResponse {
     match req {
+        // Token already checked by `authorize`.
+        RpcRequest::Auth { .. } => RpcResponse::Authenticated,
+        // The handler switches codecs after writing this reply.
+        RpcRequest::SetFraming { framing } => RpcResponse::FramingSet { framing },
         RpcRequest::Ping => RpcResponse::Pong {
             uptime_s: state.started_at.elapsed().as_secs(),
         },
         RpcRequest::Version => RpcResponse::Version {
             daemon: ca_lib::version().to_owned(),
             protocol: PROTOCOL_VERSION,
+            started_at: state.started_at_unix,
+            pid: std::process::id(),
         },
         RpcRequest::ArchitectRegister { .. } => RpcResponse::Error {
+            code: ErrorCode::NotImplemented,
             message: "ArchitectRegister not yet implemented (lands in M2)".to_owned(),
         },
         RpcRequest::TaskList { .. } => RpcResponse::Error {
+            code: ErrorCode::NotImplemented,
             message: "TaskList not yet implemented (lands in M3)".to_owned(),
         },
         RpcRequest::TaskGet { .. } => RpcResponse::Error {
+            code: ErrorCode::NotImplemented,
             message: "TaskGet not yet implemented (lands in M3)".to_owned(),
         },
+        RpcRequest::Diagnostics => RpcResponse::Diagnostics {
+            entries: state.diagnostics.recent(),
+        },
+        RpcRequest::GetConfig => RpcResponse::Config {
+            config: state.config.effective(),
+        },
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use tokio::io::AsyncBufReadExt;
+
     use super::*;
 
     fn fresh_state() -> AppState {
         AppState {
             started_at: Instant::now(),
+            started_at_unix: 1_700_000_000,
+            config: Config::parse_without_env(["ca-daemon"]),
+            auth_token: None,
+            connections: Connections::default(),
+            diagnostics: Diagnostics::default(),
         }
     }
 
@@ -109,19 +496,68 @@ mod tests {
         }
     }
 
+    #[test]
+    fn dispatch_get_config_matches_state_and_omits_token() {
+        let state = AppState {
+            auth_token: Some("s3cret".to_owned()),
+            config: Config::parse_without_env([
+                "ca-daemon",
+                "--require-auth",
+                "--max-connections",
+                "9",
+            ]),
+            ..fresh_state()
+        };
+        match dispatch(RpcRequest::GetConfig, &state) {
+            RpcResponse::Config { config } => {
+                assert_eq!(config, state.config.effective());
+                assert_eq!(config["max_connections"], 9);
+                assert_eq!(config["require_auth"], true);
+                assert!(!config.to_string().contains("s3cret"));
+            }
+            other => panic!("expected Config, got {other:?}"),
+        }
+    }
+
     #[test]
     fn dispatch_version_returns_daemon_and_protocol() {
         let resp = dispatch(RpcRequest::Version, &fresh_state());
         match resp {
-            RpcResponse::Version { daemon, protocol } => {
+            RpcResponse::Version {
+                daemon,
+                protocol,
+                started_at,
+                pid,
+            } => {
                 assert!(!daemon.is_empty());
                 assert_eq!(protocol, PROTOCOL_VERSION);
                 assert_eq!(daemon, ca_lib::version());
+                assert_eq!(started_at, 1_700_000_000);
+                assert_eq!(pid, std::process::id());
             }
             other => panic!("expected Version, got {other:?}"),
         }
     }
 
+    #[test]
+    fn authorize_requires_matching_token_when_configured() {
+        let state = AppState {
+            auth_token: Some("s3cret".to_owned()),
+            ..fresh_state()
+        };
+        let mut authed = false;
+        assert!(!authorize(&RpcRequest::Ping, &state, &mut authed));
+        let wrong = RpcRequest::Auth {
+            token: "nope".into(),
+        };
+        assert!(!authorize(&wrong, &state, &mut authed));
+        let right = RpcRequest::Auth {
+            token: "s3cret".into(),
+        };
+        assert!(authorize(&right, &state, &mut authed));
+        assert!(authorize(&RpcRequest::Ping, &state, &mut authed));
+    }
+
     #[test]
     fn dispatch_unimplemented_request_returns_error() {
         let resp = dispatch(
@@ -131,10 +567,51 @@ mod tests {
             &fresh_state(),
         );
         match resp {
-            RpcResponse::Error { message } => {
+            RpcResponse::Error { code, message } => {
+                assert_eq!(code, ErrorCode::NotImplemented);
                 assert!(message.contains("not yet"), "message: {message}");
             }
             other => panic!("expected Error, got {other:?}"),
         }
     }
+
+    #[tokio::test]
+    async fn concurrent_senders_never_interleave_frames() {
+        // A tiny pipe forces every frame through several partial writes.
+        let (server, client) = tokio::io::duplex(16);
+        let (tx, rx) = mpsc::channel(4);
+        static NATIVE: AtomicBool = AtomicBool::new(false);
+        let writer = tokio::spawn(write_loop(server, rx, &NATIVE));
+
+        let senders: Vec<_> = (0..4)
+            .map(|n| {
+                let tx = tx.clone();
+                tokio::spawn(async move {
+                    for i in 0..25 {
+                        let message = format!("sender {n} frame {i} {}", "x".repeat(200));
+                        let error = RpcResponse::Error {
+                            code: ErrorCode::BadRequest,
+                            message,
+                        };
+                        tx.send(Outbound::Native(error)).await.unwrap();
+                    }
+                })
+            })
+            .collect();
+        drop(tx);
+
+        let mut lines = BufReader::new(client).lines();
+        let mut seen = 0;
+        while let Some(line) = lines.next_line().await.unwrap() {
+            let resp: RpcResponse = serde_json::from_str(&line)
+                .unwrap_or_else(|e| panic!("interleaved frame {line:?}: {e}"));
+            assert!(matches!(resp, RpcResponse::Error { .. }));
+            seen += 1;
+        }
+        assert_eq!(seen, 100);
+        for s in senders {
+            s.await.unwrap();
+        }
+        writer.await.unwrap();
+    }
 }
@@ -1,57 +1,72 @@
 //! Daemon socket lifecycle: bind, accept, drain, cleanup.
 //!
-//! Per-connection RPC dispatch lives in [`crate::rpc`]. This module owns
-//! the listener, the signal-driven shutdown, and socket-file cleanup.
+//! Per-connection RPC dispatch lives in [`crate::rpc`]; the platform listener
+//! lives in [`crate::transport`]. This module owns the accept loop, the
+//! signal-driven shutdown, and cleanup.
 
-use std::os::unix::fs::PermissionsExt;
 use std::path::PathBuf;
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use anyhow::{Context, Result, bail};
-use tokio::net::UnixListener;
-use tokio::signal::unix::{SignalKind, signal};
-use tokio::sync::Notify;
+use anyhow::{Context, Result};
+use ca_lib::{DiagnosticKind, RpcResponse};
+use tokio::sync::{Notify, Semaphore};
 use tokio::task::JoinSet;
 use tracing::{debug, error, info, warn};
 
+use crate::auth;
+use crate::config::Config;
+use crate::diagnostics::{Diagnostics, Run};
+use crate::registry::Connections;
 use crate::rpc::{self, AppState};
+use crate::transport::{PlatformTransport, Transport};
 
 /// Maximum time to wait for in-flight handlers during shutdown before
 /// abandoning them.
 const DRAIN_TIMEOUT: Duration = Duration::from_secs(2);
 
-/// Bind the UDS at `path`, accept connections, drain on SIGTERM/SIGINT,
-/// remove the socket file on exit.
+/// Bind the configured socket (a named pipe on Windows), accept connections,
+/// drain on SIGTERM/SIGINT (Ctrl-C on Windows), clean up on exit.
 ///
-/// Errors if `path` already exists (no silent overwrite of a live socket).
-pub async fn serve(path: PathBuf) -> Result<()> {
-    if path.exists() {
-        bail!(
-            "socket already exists at {} (is another daemon running?)",
-            path.display()
-        );
-    }
-    if let Some(parent) = path.parent()
-        && !parent.exists()
-    {
-        std::fs::create_dir_all(parent)
-            .with_context(|| format!("creating parent dir {}", parent.display()))?;
-    }
-
-    let listener =
-        UnixListener::bind(&path).with_context(|| format!("binding {}", path.display()))?;
-    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600))
-        .with_context(|| format!("setting permissions on {}", path.display()))?;
+/// Errors if the path already exists (no silent overwrite of a live socket).
+pub async fn serve(config: Config) -> Result<()> {
+    let path = config
+        .socket_path()
+        .context("no socket path: HOME is not set; pass --data-dir or --socket")?;
+    // Load the token before touching the socket so a bad token file fails
+    // fast without leaving a stale socket behind.
+    let auth_token = if config.require_auth {
+        let token_path = config
+            .token_path()
+            .context("no token file: HOME is not set; pass --data-dir or --token-file")?;
+        Some(auth::load_token(&token_path)?)
+    } else {
+        None
+    };
+    let mut listener = PlatformTransport::bind(&path)?;
 
     let started_at = std::time::Instant::now();
-    let state = Arc::new(AppState { started_at });
+    let started_at_unix = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs() as i64);
+    let state = Arc::new(AppState {
+        started_at,
+        started_at_unix,
+        config,
+        auth_token,
+        connections: Connections::default(),
+        diagnostics: Diagnostics::default(),
+    });
     info!(socket = %path.display(), version = ca_lib::version(), "ca-daemon listening");
 
     let shutdown = Arc::new(Notify::new());
-    spawn_signal_listener(shutdown.clone());
+    spawn_signal_listener(shutdown.clone(), path.clone());
 
     let mut conns: JoinSet<()> = JoinSet::new();
+    let max_connections = state.config.max_connections;
+    let slots = (max_connections > 0).then(|| Arc::new(Semaphore::new(max_connections as usize)));
+    let mut busy_run = Run::new(DiagnosticKind::Busy);
+    let mut accept_run = Run::new(DiagnosticKind::Accept);
 
     loop {
         tokio::select! {
@@ -61,15 +76,38 @@ pub async fn serve(path: PathBuf) -> Result<()> {
                 break;
             }
             accept = listener.accept() => match accept {
-                Ok((stream, _)) => {
-                    debug!("connection accepted");
-                    conns.spawn(rpc::handle_connection(stream, state.clone()));
+                Ok(stream) => match slots.clone().map(Semaphore::try_acquire_owned) {
+                    Some(Err(_)) => {
+                        accept_run.end();
+                        warn!(max_connections, "connection limit reached; rejecting client");
+                        busy_run.record(&state.diagnostics, || {
+                            format!("rejected clients at the {max_connections}-connection limit")
+                        });
+                        conns.spawn(rpc::reject_busy(stream, max_connections));
+                    }
+                    permit => {
+                        accept_run.end();
+                        busy_run.end();
+                        let state = state.clone();
+                        conns.spawn(async move {
+                            rpc::handle_connection(stream, state).await;
+                            drop(permit);
+                        });
+                    }
+                },
+                Err(e) => {
+                    warn!(error = %e, "accept error");
+                    accept_run.record(&state.diagnostics, || e.to_string());
                 }
-                Err(e) => warn!(error = %e, "accept error"),
             },
         }
     }
 
+    // Tell connected clients before their handlers wind down; each writer
+    // closes its connection after delivering the notice.
+    let notified = state.connections.broadcast(&RpcResponse::ShuttingDown);
+    debug!(notified, "shutdown notice pushed");
+
     // Drain in-flight handlers, bounded.
     let drain_outcome = tokio::time::timeout(DRAIN_TIMEOUT, async {
         while let Some(res) = conns.join_next().await {
@@ -86,10 +124,7 @@ pub async fn serve(path: PathBuf) -> Result<()> {
         while conns.join_next().await.is_some() {}
     }
 
-    match std::fs::remove_file(&path) {
-        Ok(()) => info!(socket = %path.display(), "socket removed"),
-        Err(e) => warn!(error = %e, socket = %path.display(), "could not remove socket file"),
-    }
+    listener.cleanup(&path);
 
     info!(
         uptime_s = started_at.elapsed().as_secs(),
@@ -98,7 +133,16 @@ pub async fn serve(path: PathBuf) -> Result<()> {
     Ok(())
 }
 
-fn spawn_signal_listener(shutdown: Arc<Notify>) {
+/// Exit code when a second signal cuts the drain short.
+const FORCED_EXIT_CODE: i32 = 1;
+
+/// First SIGTERM/SIGINT starts a graceful shutdown; a second one while
+/// draining exits immediately, removing the socket file first so the next
+/// start isn't refused.
+#[cfg(unix)]
+fn spawn_signal_listener(shutdown: Arc<Notify>, path: PathBuf) {
+    use tokio::signal::unix::{SignalKind, signal};
+
     tokio::spawn(async move {
         let mut sigterm = match signal(SignalKind::terminate()) {
             Ok(s) => s,
@@ -119,5 +163,36 @@ fn spawn_signal_listener(shutdown: Arc<Notify>) {
             _ = sigint.recv() => info!("SIGINT received"),
         }
         shutdown.notify_waiters();
+
+        tokio::select! {
+            _ = sigterm.recv() => {}
+            _ = sigint.recv() => {}
+        }
+        warn!("second signal during shutdown; exiting immediately");
+        if let Err(e) = std::fs::remove_file(&path) {
+            warn!(error = %e, socket = %path.display(), "could not remove socket file");
+        }
+        std::process::exit(FORCED_EXIT_CODE);
+    });
+}
+
+/// First Ctrl-C starts a graceful shutdown; a second one exits immediately.
+/// Pipes leave nothing on disk, so `_path` needs no cleanup.
+#[cfg(windows)]
+fn spawn_signal_listener(shutdown: Arc<Notify>, _path: PathBuf) {
+    tokio::spawn(async move {
+        match tokio::signal::ctrl_c().await {
+            Ok(()) => info!("Ctrl-C received"),
+            Err(e) => {
+                error!(error = %e, "registering Ctrl-C handler");
+                return;
+            }
+        }
+        shutdown.notify_waiters();
+
+        if tokio::signal::ctrl_c().await.is_ok() {
+            warn!("second Ctrl-C during shutdown; exiting immediately");
+            std::process::exit(FORCED_EXIT_CODE);
+        }
     });
 }
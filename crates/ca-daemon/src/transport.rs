@@ -0,0 +1,197 @@
+//! Platform listeners behind one accept interface.
+//!
+//! Unix binds a domain socket; Windows serves a named pipe. [`crate::socket`]
+//! drives either through [`Transport`], and [`crate::rpc::handle_connection`]
+//! only needs an `AsyncRead + AsyncWrite` stream, so the protocol and handlers
+//! are identical on both.
+
+use std::future::Future;
+use std::io;
+use std::path::Path;
+
+use anyhow::Result;
+use tokio::io::{AsyncRead, AsyncWrite};
+
+/// A bound listener that yields one stream per client.
+pub trait Transport: Sized {
+    type Stream: AsyncRead + AsyncWrite + Send + 'static;
+
+    /// Bind at `path`. Errors if another daemon already owns it.
+    fn bind(path: &Path) -> Result<Self>;
+
+    /// Wait for the next client.
+    fn accept(&mut self) -> impl Future<Output = io::Result<Self::Stream>> + Send;
+
+    /// Release whatever `bind` left behind on disk.
+    fn cleanup(self, path: &Path);
+}
+
+#[cfg(unix)]
+pub use self::unix::UdsTransport as PlatformTransport;
+#[cfg(windows)]
+pub use self::windows::PipeTransport as PlatformTransport;
+
+#[cfg(unix)]
+mod unix {
+    use std::io;
+    use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+    use std::path::Path;
+
+    use anyhow::{Context, Result, bail};
+    use tokio::net::{UnixListener, UnixStream};
+    use tracing::{info, warn};
+
+    use super::Transport;
+
+    /// Unix domain socket, mode 0600.
+    pub struct UdsTransport(UnixListener);
+
+    impl Transport for UdsTransport {
+        type Stream = UnixStream;
+
+        fn bind(path: &Path) -> Result<Self> {
+            if path.as_os_str().is_empty() {
+                bail!("socket path is empty");
+            }
+            // Never remove or bind over whatever is there: a live socket
+            // means another daemon, anything else is not ours.
+            if let Ok(meta) = std::fs::symlink_metadata(path) {
+                let kind = meta.file_type();
+                if kind.is_socket() {
+                    bail!(
+                        "socket already exists at {} (is another daemon running?)",
+                        path.display()
+                    );
+                }
+                let what = if kind.is_dir() {
+                    "a directory"
+                } else if kind.is_symlink() {
+                    "a symlink"
+                } else {
+                    "a regular file"
+                };
+                bail!(
+                    "{} already exists and is {what}, not a socket; refusing to replace it",
+                    path.display()
+                );
+            }
+            let parent = path.parent().unwrap_or(Path::new("."));
+            if !parent.exists() {
+                std::fs::create_dir_all(parent).map_err(|e| {
+                    write_error(
+                        parent,
+                        e,
+                        format!("creating parent dir {}", parent.display()),
+                    )
+                })?;
+            }
+
+            let listener = UnixListener::bind(path)
+                .map_err(|e| write_error(parent, e, format!("binding {}", path.display())))?;
+            std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))
+                .with_context(|| format!("setting permissions on {}", path.display()))?;
+            Ok(Self(listener))
+        }
+
+        async fn accept(&mut self) -> io::Result<UnixStream> {
+            self.0.accept().await.map(|(stream, _)| stream)
+        }
+
+        fn cleanup(self, path: &Path) {
+            drop(self.0);
+            match std::fs::remove_file(path) {
+                Ok(()) => info!(socket = %path.display(), "socket removed"),
+                Err(e) => {
+                    warn!(error = %e, socket = %path.display(), "could not remove socket file");
+                }
+            }
+        }
+    }
+
+    /// Say what to fix when the storage under `dir` refuses a write;
+    /// anything else keeps the plain `doing` context.
+    fn write_error(dir: &Path, e: io::Error, doing: String) -> anyhow::Error {
+        let why = match e.kind() {
+            io::ErrorKind::PermissionDenied => "permission denied",
+            io::ErrorKind::ReadOnlyFilesystem => "read-only filesystem",
+            io::ErrorKind::StorageFull => "no space left on device",
+            _ => return anyhow::Error::new(e).context(doing),
+        };
+        anyhow::Error::new(e).context(format!("cannot write to {} ({why})", dir.display()))
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn storage_failures_name_the_directory_and_cause() {
+            let dir = Path::new("/data/ca");
+            for (kind, why) in [
+                (io::ErrorKind::PermissionDenied, "permission denied"),
+                (io::ErrorKind::ReadOnlyFilesystem, "read-only filesystem"),
+                (io::ErrorKind::StorageFull, "no space left on device"),
+            ] {
+                let e = write_error(dir, kind.into(), "binding x".to_owned());
+                assert_eq!(e.to_string(), format!("cannot write to /data/ca ({why})"));
+            }
+            let e = write_error(dir, io::ErrorKind::AddrInUse.into(), "binding x".to_owned());
+            assert_eq!(e.to_string(), "binding x");
+        }
+    }
+}
+
+#[cfg(windows)]
+mod windows {
+    use std::ffi::OsString;
+    use std::io;
+    use std::path::Path;
+
+    use anyhow::{Context, Result, bail};
+    use tokio::net::windows::named_pipe::{NamedPipeServer, ServerOptions};
+
+    use super::Transport;
+
+    /// Named pipe (`\\.\pipe\...`). Each accept hands out the connected
+    /// instance and creates a fresh one for the next client.
+    pub struct PipeTransport {
+        name: OsString,
+        next: NamedPipeServer,
+    }
+
+    impl Transport for PipeTransport {
+        type Stream = NamedPipeServer;
+
+        fn bind(path: &Path) -> Result<Self> {
+            if path.as_os_str().is_empty() {
+                bail!("pipe name is empty");
+            }
+            let name = path.as_os_str().to_owned();
+            // `first_pipe_instance` fails if another daemon already serves
+            // this name — the pipe analogue of the stale-socket check.
+            let next = ServerOptions::new()
+                .first_pipe_instance(true)
+                .reject_remote_clients(true)
+                .create(&name)
+                .with_context(|| {
+                    format!(
+                        "pipe already exists at {} (is another daemon running?)",
+                        path.display()
+                    )
+                })?;
+            Ok(Self { name, next })
+        }
+
+        async fn accept(&mut self) -> io::Result<NamedPipeServer> {
+            self.next.connect().await?;
+            let fresh = ServerOptions::new()
+                .reject_remote_clients(true)
+                .create(&self.name)?;
+            Ok(std::mem::replace(&mut self.next, fresh))
+        }
+
+        fn cleanup(self, _path: &Path) {
+            // Pipes vanish with their last handle; nothing on disk.
+        }
+    }
+}
@@ -0,0 +1,125 @@
+//! Integration tests for `--require-auth`.
+//!
+//! Each test writes a 0600 token file into its tempdir and points the daemon
+//! at it with `--token-file`.
+
+#![cfg(unix)]
+
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::time::Duration;
+
+use ca_lib::{ErrorCode, RpcRequest, RpcResponse};
+use tempfile::tempdir;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::UnixStream;
+use tokio::process::Child;
+
+mod common;
+use common::{spawn_daemon_with_args, wait_for_socket};
+
+const TOKEN: &str = "correct-horse-battery-staple";
+
+async fn spawn_with_auth(dir: &Path) -> (Child, std::path::PathBuf) {
+    let socket = dir.join("ca.sock");
+    let token_file = dir.join("ca.token");
+    std::fs::write(&token_file, format!("{TOKEN}\n")).unwrap();
+    std::fs::set_permissions(&token_file, std::fs::Permissions::from_mode(0o600)).unwrap();
+    let child = spawn_daemon_with_args(
+        &socket,
+        &[
+            "--require-auth",
+            "--token-file",
+            token_file.to_str().unwrap(),
+        ],
+    );
+    assert!(wait_for_socket(&socket, Duration::from_secs(3)).await);
+    (child, socket)
+}
+
+/// Send requests in order on one connection and collect one response per
+/// request; stops early if the daemon closes the connection.
+async fn exchange(socket: &Path, reqs: &[RpcRequest]) -> Vec<RpcResponse> {
+    let stream = UnixStream::connect(socket).await.expect("connect");
+    let (read_half, mut write_half) = stream.into_split();
+    let mut reader = BufReader::new(read_half);
+    let mut out = Vec::new();
+    for req in reqs {
+        let line = format!("{}\n", serde_json::to_string(req).unwrap());
+        if write_half.write_all(line.as_bytes()).await.is_err() {
+            break;
+        }
+        let mut buf = String::new();
+        let n = tokio::time::timeout(Duration::from_secs(2), reader.read_line(&mut buf))
+            .await
+            .expect("response timed out")
+            .unwrap_or(0);
+        if n == 0 {
+            break;
+        }
+        out.push(serde_json::from_str(buf.trim_end()).expect("parse"));
+    }
+    out
+}
+
+#[tokio::test]
+async fn correct_token_unlocks_the_connection() {
+    let dir = tempdir().unwrap();
+    let (mut child, socket) = spawn_with_auth(dir.path()).await;
+
+    let auth = RpcRequest::Auth {
+        token: TOKEN.to_owned(),
+    };
+    let resps = exchange(&socket, &[auth, RpcRequest::Ping]).await;
+    assert!(matches!(resps[0], RpcResponse::Authenticated), "{resps:?}");
+    assert!(matches!(resps[1], RpcResponse::Pong { .. }), "{resps:?}");
+
+    child.kill().await.ok();
+    let _ = child.wait().await;
+}
+
+#[tokio::test]
+async fn wrong_token_is_rejected_and_connection_closed() {
+    let dir = tempdir().unwrap();
+    let (mut child, socket) = spawn_with_auth(dir.path()).await;
+
+    let auth = RpcRequest::Auth {
+        token: "guess".to_owned(),
+    };
+    let resps = exchange(&socket, &[auth, RpcRequest::Ping]).await;
+    assert_eq!(resps.len(), 1, "connection should close: {resps:?}");
+    assert!(
+        matches!(
+            resps[0],
+            RpcResponse::Error {
+                code: ErrorCode::Unauthorized,
+                ..
+            }
+        ),
+        "{resps:?}"
+    );
+
+    child.kill().await.ok();
+    let _ = child.wait().await;
+}
+
+#[tokio::test]
+async fn request_before_auth_is_rejected() {
+    let dir = tempdir().unwrap();
+    let (mut child, socket) = spawn_with_auth(dir.path()).await;
+
+    let resps = exchange(&socket, &[RpcRequest::Ping]).await;
+    assert!(
+        matches!(
+            resps[0],
+            RpcResponse::Error {
+                code: ErrorCode::Unauthorized,
+                ..
+            }
+        ),
+        "{resps:?}"
+    );
+
+    child.kill().await.ok();
+    let _ = child.wait().await;
+}
@@ -0,0 +1,146 @@
+//! Integration tests for `ca_lib::Client` against a real daemon.
+//!
+//! The client is blocking, so each test drives it from `spawn_blocking`
+//! while the daemon runs as a subprocess.
+
+#![cfg(unix)]
+
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use ca_lib::{Backoff, Client, ClientError, ErrorCode, ReconnectingClient};
+use tempfile::tempdir;
+
+mod common;
+use common::{send_sigterm, spawn_daemon, wait_for_socket};
+
+#[tokio::test]
+async fn client_round_trips_against_daemon() {
+    let dir = tempdir().unwrap();
+    let socket = dir.path().join("ca.sock");
+    let mut child = spawn_daemon(&socket);
+    assert!(wait_for_socket(&socket, Duration::from_secs(3)).await);
+    let pid = child.id();
+
+    tokio::task::spawn_blocking(move || {
+        let mut client = Client::connect(&socket).expect("connect");
+        client.set_timeout(Some(Duration::from_secs(2))).unwrap();
+
+        client.ping().expect("ping");
+        let v = client.version().expect("version");
+        assert_eq!(v.daemon, ca_lib::version());
+        assert_eq!(Some(v.pid), pid);
+
+        // Unimplemented requests come back typed, and the connection stays
+        // usable afterwards.
+        match client.task_get("M1-T1") {
+            Err(ClientError::Daemon { code, .. }) => assert_eq!(code, ErrorCode::NotImplemented),
+            other => panic!("expected NotImplemented, got {other:?}"),
+        }
+        client.ping().expect("ping after error");
+    })
+    .await
+    .unwrap();
+
+    child.kill().await.ok();
+    let _ = child.wait().await;
+}
+
+#[tokio::test]
+async fn connect_to_missing_socket_is_io_error() {
+    let dir = tempdir().unwrap();
+    let socket = dir.path().join("absent.sock");
+    let err = Client::connect(&socket).unwrap_err();
+    assert!(matches!(err, ClientError::Io(_)), "got {err:?}");
+}
+
+#[tokio::test]
+async fn reconnecting_client_survives_daemon_restart() {
+    let dir = tempdir().unwrap();
+    let socket = dir.path().join("ca.sock");
+    let mut first = spawn_daemon(&socket);
+    assert!(wait_for_socket(&socket, Duration::from_secs(3)).await);
+
+    let mut client = ReconnectingClient::new(&socket)
+        .with_timeout(Duration::from_secs(2))
+        .with_backoff(Backoff {
+            initial: Duration::from_millis(20),
+            max: Duration::from_millis(200),
+            attempts: 30,
+        });
+    let (mut client, v) = tokio::task::spawn_blocking(move || {
+        let v = client.with_client(|c| c.version()).expect("version");
+        (client, v)
+    })
+    .await
+    .unwrap();
+    assert_eq!(Some(v.pid), first.id());
+
+    // Stop the daemon while the client holds a connection, then start the
+    // replacement only after the client is already backing off.
+    send_sigterm(&first);
+    assert!(first.wait().await.unwrap().success());
+    let call = tokio::task::spawn_blocking(move || client.with_client(|c| c.version()));
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    let mut second = spawn_daemon(&socket);
+
+    let v = call.await.unwrap().expect("version after restart");
+    assert_eq!(Some(v.pid), second.id());
+
+    second.kill().await.ok();
+    let _ = second.wait().await;
+}
+
+#[tokio::test]
+async fn call_loop_rides_out_repeated_restarts() {
+    let dir = tempdir().unwrap();
+    let socket = dir.path().join("ca.sock");
+    let mut daemon = spawn_daemon(&socket);
+    assert!(wait_for_socket(&socket, Duration::from_secs(3)).await);
+
+    let stop = Arc::new(AtomicBool::new(false));
+    let calls = tokio::task::spawn_blocking({
+        let socket = socket.clone();
+        let stop = stop.clone();
+        move || {
+            let mut client = ReconnectingClient::new(&socket)
+                .with_timeout(Duration::from_secs(2))
+                .with_backoff(Backoff {
+                    initial: Duration::from_millis(20),
+                    max: Duration::from_millis(200),
+                    attempts: 30,
+                });
+            let mut pids = HashSet::new();
+            while !stop.load(Ordering::Relaxed) {
+                // Every call must succeed, however the restart lands.
+                pids.insert(client.with_client(|c| c.version()).expect("version").pid);
+                std::thread::sleep(Duration::from_millis(10));
+            }
+            pids
+        }
+    });
+
+    let mut served = HashSet::from([daemon.id().unwrap()]);
+    for _ in 0..5 {
+        tokio::time::sleep(Duration::from_millis(150)).await;
+        // Signal without waiting: the loop keeps calling through the drain.
+        send_sigterm(&daemon);
+        assert!(daemon.wait().await.unwrap().success());
+        daemon = spawn_daemon(&socket);
+        served.insert(daemon.id().unwrap());
+        assert!(wait_for_socket(&socket, Duration::from_secs(3)).await);
+    }
+    tokio::time::sleep(Duration::from_millis(150)).await;
+    stop.store(true, Ordering::Relaxed);
+
+    let pids = calls.await.unwrap();
+    assert_eq!(
+        pids, served,
+        "every daemon instance should have been reached"
+    );
+
+    daemon.kill().await.ok();
+    let _ = daemon.wait().await;
+}
@@ -18,10 +18,28 @@ use tokio::process::{Child, Command};
 /// Path to the `ca-daemon` binary, set by Cargo at build time.
 pub const DAEMON_BIN: &str = env!("CARGO_BIN_EXE_ca-daemon");
 
+/// A `ca-daemon` command with every `CA_*` variable from the test runner's
+/// environment removed, so only what the test sets reaches the daemon.
+pub fn daemon_command() -> Command {
+    let mut cmd = Command::new(DAEMON_BIN);
+    for (key, _) in std::env::vars_os() {
+        if key.to_string_lossy().starts_with("CA_") {
+            cmd.env_remove(key);
+        }
+    }
+    cmd
+}
+
 /// Spawn the daemon as a child process pointed at the given socket path.
 /// Stdout / stderr are silenced unless the caller swaps them in.
 pub fn spawn_daemon(socket: &Path) -> Child {
-    Command::new(DAEMON_BIN)
+    spawn_daemon_with_args(socket, &[])
+}
+
+/// Like [`spawn_daemon`] but with extra command-line flags.
+pub fn spawn_daemon_with_args(socket: &Path, args: &[&str]) -> Child {
+    daemon_command()
+        .args(args)
         .env("CA_SOCKET_PATH", socket)
         .stdout(Stdio::null())
         .stderr(Stdio::null())
@@ -43,6 +61,7 @@ pub async fn wait_for_socket(path: &Path, timeout: Duration) -> bool {
 }
 
 /// Send SIGTERM via libc (`tokio::process::Child::kill` is SIGKILL).
+#[cfg(unix)]
 pub fn send_sigterm(child: &Child) {
     let pid = child.id().expect("child should have a pid");
     let rc = unsafe { libc::kill(pid as libc::pid_t, libc::SIGTERM) };
@@ -0,0 +1,48 @@
+//! Smoke test for the Windows named-pipe transport.
+//!
+//! The Unix suites cover the protocol itself; this only checks that the
+//! daemon serves it over a pipe.
+
+#![cfg(windows)]
+
+use std::path::Path;
+use std::time::Duration;
+
+use ca_lib::{RpcRequest, RpcResponse};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::windows::named_pipe::{ClientOptions, NamedPipeClient};
+
+mod common;
+use common::spawn_daemon;
+
+/// Retry until the daemon has created the pipe.
+async fn connect(name: &str, timeout: Duration) -> NamedPipeClient {
+    let deadline = tokio::time::Instant::now() + timeout;
+    loop {
+        match ClientOptions::new().open(name) {
+            Ok(client) => return client,
+            Err(_) if tokio::time::Instant::now() < deadline => {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+            Err(e) => panic!("pipe {name} never appeared: {e}"),
+        }
+    }
+}
+
+#[tokio::test]
+async fn ping_over_named_pipe() {
+    let name = format!(r"\\.\pipe\ca-test-{}", std::process::id());
+    let mut child = spawn_daemon(Path::new(&name));
+
+    let client = connect(&name, Duration::from_secs(5)).await;
+    let (reader, mut writer) = tokio::io::split(client);
+    let line = serde_json::to_string(&RpcRequest::Ping).unwrap() + "\n";
+    writer.write_all(line.as_bytes()).await.unwrap();
+
+    let mut resp = String::new();
+    BufReader::new(reader).read_line(&mut resp).await.unwrap();
+    let resp: RpcResponse = serde_json::from_str(resp.trim()).unwrap();
+    assert!(matches!(resp, RpcResponse::Pong { .. }), "got {resp:?}");
+
+    child.kill().await.unwrap();
+}
@@ -3,15 +3,18 @@
 //! Spawns the daemon as a subprocess, connects via `UnixStream`, exercises
 //! the wire protocol end-to-end. Each line is one request or one response.
 
+#![cfg(unix)]
+
 use std::time::Duration;
 
-use ca_lib::{RpcRequest, RpcResponse};
+use ca_lib::{DiagnosticKind, ErrorCode, Framing, RpcRequest, RpcResponse};
 use tempfile::tempdir;
-use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
 use tokio::net::UnixStream;
+use tokio::process::Child;
 
 mod common;
-use common::{spawn_daemon, wait_for_socket};
+use common::{daemon_command, spawn_daemon, spawn_daemon_with_args, wait_for_socket};
 
 /// Send a single RPC request line, read one response line, return parsed.
 async fn round_trip(stream: &mut UnixStream, req: &RpcRequest) -> RpcResponse {
@@ -56,9 +59,24 @@ async fn version_handshake_returns_daemon_and_protocol() {
     let mut stream = UnixStream::connect(&socket).await.expect("connect");
     let resp = round_trip(&mut stream, &RpcRequest::Version).await;
     match resp {
-        RpcResponse::Version { daemon, protocol } => {
+        RpcResponse::Version {
+            daemon,
+            protocol,
+            started_at,
+            pid,
+        } => {
+            assert_eq!(daemon, env!("CARGO_PKG_VERSION"));
             assert_eq!(daemon, ca_lib::version());
             assert_eq!(protocol, 1);
+            assert_eq!(Some(pid), child.id());
+            let now = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_secs() as i64;
+            assert!(
+                (now - 10..=now).contains(&started_at),
+                "started_at {started_at} should be just before {now}"
+            );
         }
         other => panic!("expected Version, got {other:?}"),
     }
@@ -99,7 +117,7 @@ async fn unknown_request_returns_error() {
     let raw = round_trip_raw(&mut stream, b"{\"type\":\"non_existent_request\"}\n").await;
     let parsed: RpcResponse = serde_json::from_str(raw.trim_end()).expect("parse");
     match parsed {
-        RpcResponse::Error { message } => {
+        RpcResponse::Error { message, .. } => {
             assert!(
                 message.to_lowercase().contains("parse")
                     || message.to_lowercase().contains("unknown"),
@@ -113,6 +131,36 @@ async fn unknown_request_returns_error() {
     let _ = child.wait().await;
 }
 
+#[tokio::test]
+async fn future_request_type_is_bad_request_and_connection_survives() {
+    let dir = tempdir().unwrap();
+    let socket = dir.path().join("ca.sock");
+    let mut child = spawn_daemon(&socket);
+    assert!(wait_for_socket(&socket, Duration::from_secs(3)).await);
+
+    // A message a newer client might send: unknown tag plus fields.
+    let mut stream = UnixStream::connect(&socket).await.expect("connect");
+    let raw = round_trip_raw(
+        &mut stream,
+        b"{\"type\":\"subscribe\",\"topics\":[\"tasks\"]}\n",
+    )
+    .await;
+    let parsed: RpcResponse = serde_json::from_str(raw.trim_end()).expect("parse");
+    match parsed {
+        RpcResponse::Error { code, message } => {
+            assert_eq!(code, ErrorCode::BadRequest);
+            assert!(message.contains("subscribe"), "got {message:?}");
+        }
+        other => panic!("expected Error, got {other:?}"),
+    }
+
+    let resp = round_trip(&mut stream, &RpcRequest::Ping).await;
+    assert!(matches!(resp, RpcResponse::Pong { .. }), "got {resp:?}");
+
+    child.kill().await.ok();
+    let _ = child.wait().await;
+}
+
 #[tokio::test]
 async fn malformed_json_returns_error_not_crash() {
     let dir = tempdir().unwrap();
@@ -176,3 +224,466 @@ async fn concurrent_clients_each_get_their_own_response() {
     child.kill().await.ok();
     let _ = child.wait().await;
 }
+
+#[tokio::test]
+async fn burst_past_rate_limit_is_throttled() {
+    let dir = tempdir().unwrap();
+    let socket = dir.path().join("ca.sock");
+    let mut child = spawn_daemon_with_args(&socket, &["--max-requests-per-sec", "5"]);
+    assert!(wait_for_socket(&socket, Duration::from_secs(3)).await);
+
+    let stream = UnixStream::connect(&socket).await.expect("connect");
+    let (read_half, mut write_half) = stream.into_split();
+    let burst = "{\"type\":\"ping\"}\n".repeat(20);
+    write_half
+        .write_all(burst.as_bytes())
+        .await
+        .expect("write burst");
+
+    let mut reader = BufReader::new(read_half);
+    let (mut pongs, mut limited) = (0, 0);
+    for _ in 0..20 {
+        let mut buf = String::new();
+        tokio::time::timeout(Duration::from_secs(2), reader.read_line(&mut buf))
+            .await
+            .expect("response timed out")
+            .expect("read response");
+        match serde_json::from_str(buf.trim_end()).expect("parse") {
+            RpcResponse::Pong { .. } => pongs += 1,
+            RpcResponse::Error {
+                code: ErrorCode::RateLimited,
+                ..
+            } => limited += 1,
+            other => panic!("unexpected response: {other:?}"),
+        }
+    }
+    assert!(
+        pongs >= 5,
+        "the initial burst should be served, got {pongs}"
+    );
+    assert!(limited > 0, "expected some rate_limited replies");
+
+    // Budget refills; the same connection is served again.
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    write_half
+        .write_all(b"{\"type\":\"ping\"}\n")
+        .await
+        .expect("write ping");
+    let mut buf = String::new();
+    reader.read_line(&mut buf).await.expect("read response");
+    let resp: RpcResponse = serde_json::from_str(buf.trim_end()).expect("parse");
+    assert!(matches!(resp, RpcResponse::Pong { .. }), "got {resp:?}");
+
+    child.kill().await.ok();
+    let _ = child.wait().await;
+}
+
+#[tokio::test]
+async fn set_framing_switches_to_length_prefixed() {
+    let dir = tempdir().unwrap();
+    let socket = dir.path().join("ca.sock");
+    let mut child = spawn_daemon(&socket);
+    assert!(wait_for_socket(&socket, Duration::from_secs(3)).await);
+
+    let mut stream = UnixStream::connect(&socket).await.expect("connect");
+    let switch = RpcRequest::SetFraming {
+        framing: Framing::LengthPrefixed,
+    };
+    // The acknowledgement still arrives as a line.
+    let resp = round_trip(&mut stream, &switch).await;
+    assert_eq!(
+        resp,
+        RpcResponse::FramingSet {
+            framing: Framing::LengthPrefixed
+        }
+    );
+
+    // Pretty-printed JSON contains raw newlines; fine once framed.
+    let body = serde_json::to_vec_pretty(&RpcRequest::Ping).unwrap();
+    assert!(body.contains(&b'\n'));
+    let mut frame = (body.len() as u32).to_be_bytes().to_vec();
+    frame.extend_from_slice(&body);
+    stream.write_all(&frame).await.expect("write frame");
+
+    let mut prefix = [0u8; 4];
+    tokio::time::timeout(Duration::from_secs(2), stream.read_exact(&mut prefix))
+        .await
+        .expect("response timed out")
+        .expect("read prefix");
+    let mut reply = vec![0u8; u32::from_be_bytes(prefix) as usize];
+    stream.read_exact(&mut reply).await.expect("read body");
+    let resp: RpcResponse = serde_json::from_slice(&reply).expect("parse");
+    assert!(matches!(resp, RpcResponse::Pong { .. }), "got {resp:?}");
+
+    child.kill().await.ok();
+    let _ = child.wait().await;
+}
+
+#[tokio::test]
+async fn set_framing_notification_still_switches() {
+    let dir = tempdir().unwrap();
+    let socket = dir.path().join("ca.sock");
+    let mut child = spawn_daemon(&socket);
+    assert!(wait_for_socket(&socket, Duration::from_secs(3)).await);
+
+    let mut stream = UnixStream::connect(&socket).await.expect("connect");
+    // No id, so no acknowledgement: the very next frame is length-prefixed.
+    let notify =
+        br#"{"jsonrpc":"2.0","method":"set_framing","params":{"framing":"length_prefixed"}}"#;
+    let body = br#"{"jsonrpc":"2.0","id":2,"method":"ping"}"#;
+    let mut wire = notify.to_vec();
+    wire.push(b'\n');
+    wire.extend_from_slice(&(body.len() as u32).to_be_bytes());
+    wire.extend_from_slice(body);
+    stream.write_all(&wire).await.expect("write");
+
+    let mut prefix = [0u8; 4];
+    tokio::time::timeout(Duration::from_secs(2), stream.read_exact(&mut prefix))
+        .await
+        .expect("response timed out")
+        .expect("read prefix");
+    let mut reply = vec![0u8; u32::from_be_bytes(prefix) as usize];
+    stream.read_exact(&mut reply).await.expect("read body");
+    let resp: serde_json::Value = serde_json::from_slice(&reply).expect("parse");
+    assert_eq!(resp["id"], 2);
+    assert!(resp["result"]["uptime_s"].is_u64(), "got {resp}");
+
+    child.kill().await.ok();
+    let _ = child.wait().await;
+}
+
+#[tokio::test]
+async fn jsonrpc_call_notification_and_unknown_method() {
+    let dir = tempdir().unwrap();
+    let socket = dir.path().join("ca.sock");
+    let mut child = spawn_daemon(&socket);
+    assert!(wait_for_socket(&socket, Duration::from_secs(3)).await);
+
+    let stream = UnixStream::connect(&socket).await.expect("connect");
+    let (read_half, mut write_half) = stream.into_split();
+    let mut reader = BufReader::new(read_half);
+    // A notification (no id) gets no reply, so the first line back answers
+    // the versioned call that follows it.
+    let batch = concat!(
+        r#"{"jsonrpc":"2.0","method":"ping"}"#,
+        "\n",
+        r#"{"jsonrpc":"2.0","id":1,"method":"version"}"#,
+        "\n",
+        r#"{"jsonrpc":"2.0","id":"x","method":"list_sessions","params":{}}"#,
+        "\n",
+    );
+    write_half
+        .write_all(batch.as_bytes())
+        .await
+        .expect("write batch");
+
+    let mut next = async || {
+        let mut buf = String::new();
+        tokio::time::timeout(Duration::from_secs(2), reader.read_line(&mut buf))
+            .await
+            .expect("response timed out")
+            .expect("read response");
+        serde_json::from_str::<serde_json::Value>(buf.trim_end()).expect("parse")
+    };
+
+    let version = next().await;
+    assert_eq!(version["jsonrpc"], "2.0");
+    assert_eq!(version["id"], 1);
+    assert_eq!(version["result"]["daemon"], ca_lib::version());
+    assert!(version.get("error").is_none());
+
+    let missing = next().await;
+    assert_eq!(missing["id"], "x");
+    assert_eq!(missing["error"]["code"], -32601);
+    assert!(missing.get("result").is_none());
+
+    child.kill().await.ok();
+    let _ = child.wait().await;
+}
+
+/// Spawn the daemon at debug level with its (uncoloured) log on a pipe.
+fn spawn_daemon_with_debug_logs(socket: &std::path::Path, args: &[&str]) -> Child {
+    daemon_command()
+        .args(args)
+        .env("CA_SOCKET_PATH", socket)
+        .env("RUST_LOG", "debug")
+        .env("NO_COLOR", "1")
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::null())
+        .spawn()
+        .expect("spawn ca-daemon")
+}
+
+/// Kill a daemon from [`spawn_daemon_with_debug_logs`] and return its log.
+async fn kill_and_collect_logs(mut child: Child) -> String {
+    child.kill().await.ok();
+    let mut logs = String::new();
+    child
+        .stdout
+        .take()
+        .unwrap()
+        .read_to_string(&mut logs)
+        .await
+        .unwrap();
+    let _ = child.wait().await;
+    logs
+}
+
+#[tokio::test]
+async fn request_logs_carry_the_connection_id() {
+    let dir = tempdir().unwrap();
+    let socket = dir.path().join("ca.sock");
+    let child = spawn_daemon_with_debug_logs(&socket, &[]);
+    assert!(wait_for_socket(&socket, Duration::from_secs(3)).await);
+
+    // Connection 1 sends two requests, connection 2 one.
+    let mut first = UnixStream::connect(&socket).await.expect("connect");
+    round_trip(&mut first, &RpcRequest::Ping).await;
+    round_trip(&mut first, &RpcRequest::Version).await;
+    let mut second = UnixStream::connect(&socket).await.expect("connect");
+    round_trip(&mut second, &RpcRequest::Ping).await;
+
+    let logs = kill_and_collect_logs(child).await;
+
+    let requests: Vec<&str> = logs
+        .lines()
+        .filter(|l| l.contains("request=") && l.contains(": request"))
+        .collect();
+    let for_conn = |id: u64| {
+        requests
+            .iter()
+            .filter(|l| l.contains(&format!("conn{{id={id}}}")))
+            .count()
+    };
+    assert_eq!(requests.len(), 3, "logs:\n{logs}");
+    assert_eq!(for_conn(1), 2, "logs:\n{logs}");
+    assert_eq!(for_conn(2), 1, "logs:\n{logs}");
+}
+
+#[tokio::test]
+async fn connections_over_the_limit_get_busy_and_close() {
+    let dir = tempdir().unwrap();
+    let socket = dir.path().join("ca.sock");
+    let mut child = spawn_daemon_with_args(&socket, &["--max-connections", "2"]);
+    assert!(wait_for_socket(&socket, Duration::from_secs(3)).await);
+
+    // Fill both slots; a round-trip each proves they were accepted.
+    let mut a = UnixStream::connect(&socket).await.expect("connect a");
+    let mut b = UnixStream::connect(&socket).await.expect("connect b");
+    round_trip(&mut a, &RpcRequest::Ping).await;
+    round_trip(&mut b, &RpcRequest::Ping).await;
+
+    let extra = UnixStream::connect(&socket).await.expect("connect extra");
+    let mut reader = BufReader::new(extra);
+    let mut line = String::new();
+    tokio::time::timeout(Duration::from_secs(2), reader.read_line(&mut line))
+        .await
+        .expect("busy reply timed out")
+        .expect("read busy reply");
+    match serde_json::from_str(line.trim_end()).expect("parse") {
+        RpcResponse::Error { code, .. } => assert_eq!(code, ErrorCode::Busy),
+        other => panic!("expected Busy, got {other:?}"),
+    }
+    line.clear();
+    assert_eq!(reader.read_line(&mut line).await.expect("read eof"), 0);
+
+    // The admitted connections are unaffected.
+    for stream in [&mut a, &mut b] {
+        let resp = round_trip(stream, &RpcRequest::Ping).await;
+        assert!(matches!(resp, RpcResponse::Pong { .. }), "got {resp:?}");
+    }
+
+    child.kill().await.ok();
+    let _ = child.wait().await;
+}
+
+#[tokio::test]
+async fn diagnostics_report_rejected_frames_from_other_connections() {
+    let dir = tempdir().unwrap();
+    let socket = dir.path().join("ca.sock");
+    let mut child = spawn_daemon(&socket);
+    assert!(wait_for_socket(&socket, Duration::from_secs(3)).await);
+
+    let mut noisy = UnixStream::connect(&socket).await.expect("connect");
+    round_trip_raw(&mut noisy, b"{\"type\":\"no_such_request\"}\n").await;
+
+    let mut observer = UnixStream::connect(&socket).await.expect("connect");
+    match round_trip(&mut observer, &RpcRequest::Diagnostics).await {
+        RpcResponse::Diagnostics { entries } => {
+            let entry = entries
+                .iter()
+                .find(|e| e.kind == DiagnosticKind::BadRequest)
+                .unwrap_or_else(|| panic!("no bad_request entry in {entries:?}"));
+            assert!(entry.message.contains("no_such_request"), "got {entry:?}");
+            assert!(entry.at > 0);
+        }
+        other => panic!("expected Diagnostics, got {other:?}"),
+    }
+
+    child.kill().await.ok();
+    let _ = child.wait().await;
+}
+
+#[tokio::test]
+async fn a_throttled_burst_is_one_diagnostic() {
+    let dir = tempdir().unwrap();
+    let socket = dir.path().join("ca.sock");
+    let mut child = spawn_daemon_with_args(&socket, &["--max-requests-per-sec", "5"]);
+    assert!(wait_for_socket(&socket, Duration::from_secs(3)).await);
+
+    let mut quiet = UnixStream::connect(&socket).await.expect("connect");
+    round_trip_raw(&mut quiet, b"{\"type\":\"no_such_request\"}\n").await;
+
+    // Far more throttled frames, bad ones included, than the buffer holds.
+    let stream = UnixStream::connect(&socket).await.expect("connect");
+    let (read_half, mut write_half) = stream.into_split();
+    let burst = b"{\"type\":\"ping\"}\nnot json\n".repeat(100);
+    write_half.write_all(&burst).await.expect("write burst");
+    let mut reader = BufReader::new(read_half);
+    let mut line = String::new();
+    for _ in 0..200 {
+        line.clear();
+        reader.read_line(&mut line).await.expect("read reply");
+    }
+
+    let mut observer = UnixStream::connect(&socket).await.expect("connect");
+    match round_trip(&mut observer, &RpcRequest::Diagnostics).await {
+        RpcResponse::Diagnostics { entries } => {
+            assert!(
+                entries
+                    .iter()
+                    .any(|e| e.message.contains("no_such_request") && e.count == 1),
+                "quiet client's warning was evicted: {entries:?}"
+            );
+            let throttled: Vec<_> = entries
+                .iter()
+                .filter(|e| e.kind == DiagnosticKind::RateLimited)
+                .collect();
+            // A token refilling mid-burst may split the run; not much more.
+            assert!(throttled.len() <= 2, "got {entries:?}");
+            let total: u32 = throttled.iter().map(|e| e.count).sum();
+            assert!(total > 150, "got {throttled:?}");
+            assert!(entries.len() < 10, "got {entries:?}");
+        }
+        other => panic!("expected Diagnostics, got {other:?}"),
+    }
+
+    child.kill().await.ok();
+    let _ = child.wait().await;
+}
+
+#[tokio::test]
+async fn single_thread_runtime_serves_requests() {
+    let dir = tempdir().unwrap();
+    let socket = dir.path().join("ca.sock");
+    let mut child = spawn_daemon_with_args(&socket, &["--single-thread"]);
+    assert!(wait_for_socket(&socket, Duration::from_secs(3)).await);
+
+    let mut stream = UnixStream::connect(&socket).await.expect("connect");
+    let resp = round_trip(&mut stream, &RpcRequest::Ping).await;
+    assert!(matches!(resp, RpcResponse::Pong { .. }), "got {resp:?}");
+
+    child.kill().await.ok();
+    let _ = child.wait().await;
+}
+
+#[tokio::test]
+async fn get_config_reports_resolved_settings() {
+    let dir = tempdir().unwrap();
+    let socket = dir.path().join("ca.sock");
+    let mut child = spawn_daemon_with_args(
+        &socket,
+        &["--max-requests-per-sec", "50", "--instance", "x"],
+    );
+    assert!(wait_for_socket(&socket, Duration::from_secs(3)).await);
+
+    let mut stream = UnixStream::connect(&socket).await.expect("connect");
+    match round_trip(&mut stream, &RpcRequest::GetConfig).await {
+        RpcResponse::Config { config } => {
+            // CA_SOCKET_PATH from the environment beats --instance.
+            assert_eq!(config["socket"], socket.to_str().unwrap());
+            assert_eq!(config["instance"], "x");
+            assert_eq!(config["max_requests_per_sec"], 50);
+            assert_eq!(config["require_auth"], false);
+        }
+        other => panic!("expected Config, got {other:?}"),
+    }
+
+    child.kill().await.ok();
+    let _ = child.wait().await;
+}
+
+#[tokio::test]
+async fn get_config_without_home_reports_unresolved_paths() {
+    let dir = tempdir().unwrap();
+    let socket = dir.path().join("ca.sock");
+    // As under systemd: an explicit socket, no home directory.
+    let mut child = daemon_command()
+        .env("CA_SOCKET_PATH", &socket)
+        .env_remove("HOME")
+        .env_remove("USERPROFILE")
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .spawn()
+        .expect("spawn ca-daemon");
+    assert!(wait_for_socket(&socket, Duration::from_secs(3)).await);
+
+    let mut stream = UnixStream::connect(&socket).await.expect("connect");
+    match round_trip(&mut stream, &RpcRequest::GetConfig).await {
+        RpcResponse::Config { config } => {
+            assert_eq!(config["socket"], socket.to_str().unwrap());
+            assert_eq!(config["data_dir"], serde_json::Value::Null);
+            assert_eq!(config["token_file"], serde_json::Value::Null);
+        }
+        other => panic!("expected Config, got {other:?}"),
+    }
+    // The connection survived the request.
+    assert!(matches!(
+        round_trip(&mut stream, &RpcRequest::Ping).await,
+        RpcResponse::Pong { .. }
+    ));
+
+    child.kill().await.ok();
+    let _ = child.wait().await;
+}
+
+#[tokio::test]
+async fn client_vanishing_mid_reply_is_a_quiet_disconnect() {
+    let dir = tempdir().unwrap();
+    let socket = dir.path().join("ca.sock");
+    let child = spawn_daemon_with_debug_logs(&socket, &["--max-requests-per-sec", "0"]);
+    assert!(wait_for_socket(&socket, Duration::from_secs(3)).await);
+
+    // Flood pings without reading until the daemon's writes back up, then
+    // vanish with replies still queued.
+    let mut stream = UnixStream::connect(&socket).await.expect("connect");
+    let flood = tokio::spawn(async move {
+        let pings = b"{\"type\":\"ping\"}\n".repeat(1024);
+        while stream.write_all(&pings).await.is_ok() {}
+    });
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    flood.abort();
+    let _ = flood.await;
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    // The daemon carries on serving others.
+    let mut other = UnixStream::connect(&socket).await.expect("connect");
+    let resp = round_trip(&mut other, &RpcRequest::Ping).await;
+    assert!(matches!(resp, RpcResponse::Pong { .. }), "got {resp:?}");
+
+    let logs = kill_and_collect_logs(child).await;
+    let first: Vec<&str> = logs.lines().filter(|l| l.contains("conn{id=1}")).collect();
+    assert_eq!(
+        first
+            .iter()
+            .filter(|l| l.contains("client disconnected"))
+            .count(),
+        1,
+        "logs:\n{logs}"
+    );
+    assert!(
+        first.iter().any(|l| l.ends_with("connection closed")),
+        "logs:\n{logs}"
+    );
+    assert!(!logs.contains("WARN"), "logs:\n{logs}");
+}
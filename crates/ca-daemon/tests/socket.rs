@@ -3,15 +3,19 @@
 //! These tests spawn the actual `ca-daemon` binary as a subprocess. Each
 //! test uses a per-test tempdir for the socket so they run independently.
 
+#![cfg(unix)]
+
 use std::os::unix::fs::PermissionsExt;
 use std::process::Stdio;
 use std::time::Duration;
 
+use ca_lib::RpcResponse;
 use tempfile::tempdir;
-use tokio::process::Command;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::UnixStream;
 
 mod common;
-use common::{DAEMON_BIN, send_sigterm, spawn_daemon, wait_for_socket};
+use common::{daemon_command, send_sigterm, spawn_daemon, spawn_daemon_with_args, wait_for_socket};
 
 #[tokio::test]
 async fn daemon_creates_socket_on_start() {
@@ -59,7 +63,7 @@ async fn daemon_refuses_to_start_if_socket_exists() {
     let socket = dir.path().join("ca.sock");
     std::fs::write(&socket, b"").expect("seed socket file");
 
-    let output = Command::new(DAEMON_BIN)
+    let output = daemon_command()
         .env("CA_SOCKET_PATH", &socket)
         .stdout(Stdio::piped())
         .stderr(Stdio::piped())
@@ -77,3 +81,274 @@ async fn daemon_refuses_to_start_if_socket_exists() {
         "stderr should mention 'already exists', got: {stderr}"
     );
 }
+
+#[tokio::test]
+async fn connected_clients_get_shutdown_notice_on_sigterm() {
+    let dir = tempdir().unwrap();
+    let socket = dir.path().join("ca.sock");
+
+    let mut child = spawn_daemon(&socket);
+    assert!(wait_for_socket(&socket, Duration::from_secs(3)).await);
+    let stream = UnixStream::connect(&socket).await.expect("connect");
+    // Give the accept loop a moment to register the connection.
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    send_sigterm(&child);
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    tokio::time::timeout(Duration::from_secs(2), reader.read_line(&mut line))
+        .await
+        .expect("notice timed out")
+        .expect("read notice");
+    let notice: RpcResponse = serde_json::from_str(line.trim_end()).expect("parse");
+    assert_eq!(notice, RpcResponse::ShuttingDown);
+
+    // The daemon closes the connection itself rather than waiting for the
+    // client, so it exits well inside the drain timeout.
+    line.clear();
+    let n = reader.read_line(&mut line).await.expect("read eof");
+    assert_eq!(n, 0, "expected EOF after notice, got {line:?}");
+    let exit = tokio::time::timeout(Duration::from_secs(1), child.wait())
+        .await
+        .expect("daemon should exit promptly")
+        .expect("waitpid failed");
+    assert!(exit.success());
+}
+
+#[tokio::test]
+async fn jsonrpc_clients_get_shutdown_as_a_notification() {
+    let dir = tempdir().unwrap();
+    let socket = dir.path().join("ca.sock");
+
+    let mut child = spawn_daemon(&socket);
+    assert!(wait_for_socket(&socket, Duration::from_secs(3)).await);
+    let mut stream = UnixStream::connect(&socket).await.expect("connect");
+    stream
+        .write_all(b"{\"jsonrpc\":\"2.0\",\"id\":1,\"method\":\"ping\"}\n")
+        .await
+        .expect("write call");
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    reader.read_line(&mut line).await.expect("read reply");
+
+    send_sigterm(&child);
+    line.clear();
+    tokio::time::timeout(Duration::from_secs(2), reader.read_line(&mut line))
+        .await
+        .expect("notice timed out")
+        .expect("read notice");
+    let notice: serde_json::Value = serde_json::from_str(line.trim_end()).expect("parse");
+    assert_eq!(
+        notice,
+        serde_json::json!({"jsonrpc": "2.0", "method": "shutting_down"})
+    );
+
+    let exit = tokio::time::timeout(Duration::from_secs(1), child.wait())
+        .await
+        .expect("daemon should exit promptly")
+        .expect("waitpid failed");
+    assert!(exit.success());
+}
+
+#[tokio::test]
+async fn second_sigterm_cuts_drain_short() {
+    let dir = tempdir().unwrap();
+    let socket = dir.path().join("ca.sock");
+
+    let mut child = spawn_daemon_with_args(&socket, &["--max-requests-per-sec", "0"]);
+    assert!(wait_for_socket(&socket, Duration::from_secs(3)).await);
+
+    // A client that floods requests and never reads its replies wedges its
+    // handler, so a single SIGTERM would wait out the whole drain timeout.
+    let mut stream = UnixStream::connect(&socket).await.expect("connect");
+    let flood = tokio::spawn(async move {
+        let ping = b"{\"type\":\"ping\"}\n".repeat(1024);
+        while stream.write_all(&ping).await.is_ok() {}
+    });
+    tokio::time::sleep(Duration::from_millis(300)).await;
+
+    send_sigterm(&child);
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    send_sigterm(&child);
+
+    let exit = tokio::time::timeout(Duration::from_secs(1), child.wait())
+        .await
+        .expect("second signal should exit before the drain timeout")
+        .expect("waitpid failed");
+    assert_eq!(exit.code(), Some(1));
+    assert!(!socket.exists(), "socket should be removed on forced exit");
+    flood.abort();
+}
+
+#[tokio::test]
+async fn data_dir_flag_beats_env_for_default_socket() {
+    let env_dir = tempdir().unwrap();
+    let flag_dir = tempdir().unwrap();
+    let flag_socket = flag_dir.path().join("ca.sock");
+
+    let mut child = daemon_command()
+        .arg("--data-dir")
+        .arg(flag_dir.path())
+        .env_remove("CA_SOCKET_PATH")
+        .env("CA_DATA_DIR", env_dir.path())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .expect("spawn ca-daemon");
+
+    assert!(
+        wait_for_socket(&flag_socket, Duration::from_secs(3)).await,
+        "socket should appear under --data-dir"
+    );
+    assert!(!env_dir.path().join("ca.sock").exists());
+
+    child.kill().await.ok();
+    let _ = child.wait().await;
+}
+
+#[tokio::test]
+async fn data_dir_env_relocates_default_socket() {
+    let dir = tempdir().unwrap();
+    let socket = dir.path().join("nested").join("ca.sock");
+
+    let mut child = daemon_command()
+        .env_remove("CA_SOCKET_PATH")
+        .env("CA_DATA_DIR", dir.path().join("nested"))
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .expect("spawn ca-daemon");
+
+    assert!(
+        wait_for_socket(&socket, Duration::from_secs(3)).await,
+        "socket should appear under CA_DATA_DIR"
+    );
+
+    child.kill().await.ok();
+    let _ = child.wait().await;
+}
+
+#[tokio::test]
+async fn json_log_format_emits_one_object_per_line() {
+    let dir = tempdir().unwrap();
+    let socket = dir.path().join("ca.sock");
+
+    let child = daemon_command()
+        .args(["--log-format", "json"])
+        .env("CA_SOCKET_PATH", &socket)
+        .env("RUST_LOG", "info")
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .expect("spawn ca-daemon");
+    assert!(wait_for_socket(&socket, Duration::from_secs(3)).await);
+    send_sigterm(&child);
+    let out = child.wait_with_output().await.expect("wait");
+    assert!(out.status.success());
+
+    let logs = String::from_utf8(out.stdout).unwrap();
+    let messages: Vec<String> = logs
+        .lines()
+        .map(|l| {
+            let v: serde_json::Value =
+                serde_json::from_str(l).unwrap_or_else(|e| panic!("non-JSON log line {l:?}: {e}"));
+            v["fields"]["message"]
+                .as_str()
+                .unwrap_or_default()
+                .to_owned()
+        })
+        .collect();
+    assert!(
+        messages.iter().any(|m| m == "ca-daemon listening"),
+        "logs:\n{logs}"
+    );
+    assert!(
+        messages.iter().any(|m| m == "ca-daemon stopped"),
+        "logs:\n{logs}"
+    );
+}
+
+/// Run the daemon to completion with `socket` as its path; returns stderr.
+async fn run_expecting_failure(socket: &std::ffi::OsStr) -> String {
+    let output = daemon_command()
+        .env("CA_SOCKET_PATH", socket)
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .output()
+        .await
+        .expect("run ca-daemon");
+    assert!(!output.status.success(), "daemon should refuse to start");
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+#[tokio::test]
+async fn daemon_without_home_needs_an_explicit_path() {
+    let output = daemon_command()
+        .env_remove("HOME")
+        .env_remove("USERPROFILE")
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .output()
+        .await
+        .expect("run ca-daemon");
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("HOME is not set"), "got: {stderr}");
+    assert!(!stderr.contains("panicked"), "got: {stderr}");
+}
+
+#[tokio::test]
+async fn daemon_rejects_empty_socket_path() {
+    // Empty values never reach bind: the flag and the env var are both
+    // refused while parsing arguments.
+    let output = daemon_command()
+        .args(["--socket", ""])
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .output()
+        .await
+        .expect("run ca-daemon");
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("a value is required"), "got: {stderr}");
+
+    let stderr = run_expecting_failure("".as_ref()).await;
+    assert!(stderr.contains("a value is required"), "got: {stderr}");
+}
+
+#[tokio::test]
+async fn daemon_refuses_directory_at_socket_path() {
+    let dir = tempdir().unwrap();
+    let target = dir.path().join("ca.sock");
+    std::fs::create_dir(&target).unwrap();
+
+    let stderr = run_expecting_failure(target.as_os_str()).await;
+    assert!(
+        stderr.contains("is a directory, not a socket"),
+        "got: {stderr}"
+    );
+    assert!(target.is_dir(), "directory must be left alone");
+}
+
+#[tokio::test]
+async fn daemon_explains_unwritable_data_dir() {
+    // Root bypasses directory permissions, so there is nothing to observe.
+    if unsafe { libc::geteuid() } == 0 {
+        eprintln!("skipping: running as root");
+        return;
+    }
+    let dir = tempdir().unwrap();
+    let locked = dir.path().join("locked");
+    std::fs::create_dir(&locked).unwrap();
+    std::fs::set_permissions(&locked, std::fs::Permissions::from_mode(0o500)).unwrap();
+
+    let stderr = run_expecting_failure(locked.join("ca.sock").as_os_str()).await;
+    std::fs::set_permissions(&locked, std::fs::Permissions::from_mode(0o700)).unwrap();
+    assert!(
+        stderr.contains(&format!(
+            "cannot write to {} (permission denied)",
+            locked.display()
+        )),
+        "got: {stderr}"
+    );
+}
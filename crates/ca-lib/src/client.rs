@@ -0,0 +1,615 @@
+//! Blocking client for the daemon socket.
+//!
+//! One [`Client`] wraps one connection and speaks the native line-framed
+//! protocol. The daemon answers requests in order, so each call writes one
+//! request and reads the next response; there are no ids to correlate. The
+//! only unsolicited message is `ShuttingDown`, which surfaces as
+//! [`ClientError::ShuttingDown`]. Because replies are matched by order, a
+//! call that fails mid-exchange (timeout, short read, garbled reply) leaves
+//! the client closed rather than one reply behind.
+//!
+//! [`ReconnectingClient`] layers reconnection on top for long-lived callers
+//! that should ride out a daemon restart.
+
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+use thiserror::Error;
+
+use crate::{Diagnostic, ErrorCode, RpcRequest, RpcResponse, Task};
+
+/// Why a client call failed.
+#[derive(Debug, Error)]
+pub enum ClientError {
+    #[error("socket i/o: {0}")]
+    Io(#[from] io::Error),
+    #[error("malformed response: {0}")]
+    Decode(#[from] serde_json::Error),
+    #[error("daemon closed the connection")]
+    Closed,
+    #[error("daemon is shutting down")]
+    ShuttingDown,
+    /// The daemon answered with `RpcResponse::Error`.
+    #[error("daemon error ({code:?}): {message}")]
+    Daemon { code: ErrorCode, message: String },
+    /// A well-formed response of the wrong kind for the request.
+    #[error("unexpected response: {0:?}")]
+    Unexpected(Box<RpcResponse>),
+}
+
+impl ClientError {
+    /// Whether the connection itself is gone (daemon stopped or restarting),
+    /// as opposed to the daemon rejecting the request.
+    pub fn is_connection_lost(&self) -> bool {
+        match self {
+            Self::Closed | Self::ShuttingDown => true,
+            Self::Io(e) => matches!(
+                e.kind(),
+                io::ErrorKind::BrokenPipe
+                    | io::ErrorKind::ConnectionReset
+                    | io::ErrorKind::ConnectionAborted
+                    | io::ErrorKind::ConnectionRefused
+                    | io::ErrorKind::NotConnected
+                    | io::ErrorKind::NotFound
+                    | io::ErrorKind::UnexpectedEof
+            ),
+            _ => false,
+        }
+    }
+}
+
+/// Daemon identity as reported by `Version`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DaemonVersion {
+    pub daemon: String,
+    pub protocol: u32,
+    /// Unix seconds; 0 from a daemon too old to report it.
+    pub started_at: i64,
+    /// 0 from a daemon too old to report it.
+    pub pid: u32,
+}
+
+/// A connection to the daemon.
+#[derive(Debug)]
+pub struct Client {
+    reader: BufReader<UnixStream>,
+    writer: UnixStream,
+    /// Set once an exchange fails partway; a late reply may still be in
+    /// flight, so every later call fails with `Closed`.
+    broken: bool,
+}
+
+impl Client {
+    /// Connect to the daemon socket at `path`.
+    pub fn connect(path: impl AsRef<Path>) -> Result<Self, ClientError> {
+        Ok(Self::from_stream(UnixStream::connect(path)?)?)
+    }
+
+    fn from_stream(stream: UnixStream) -> io::Result<Self> {
+        let writer = stream.try_clone()?;
+        Ok(Self {
+            reader: BufReader::new(stream),
+            writer,
+            broken: false,
+        })
+    }
+
+    /// Bound how long a single call may block on the socket. `None` waits
+    /// forever (the default).
+    pub fn set_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.writer.set_read_timeout(timeout)?;
+        self.writer.set_write_timeout(timeout)
+    }
+
+    /// Send `req` and return the daemon's reply. `RpcResponse::Error` and
+    /// `ShuttingDown` come back as errors. After an i/o or decode failure the
+    /// connection is shut down and later calls return `Closed`.
+    pub fn call(&mut self, req: &RpcRequest) -> Result<RpcResponse, ClientError> {
+        if self.broken {
+            return Err(ClientError::Closed);
+        }
+        let mut line = serde_json::to_vec(req)?;
+        line.push(b'\n');
+
+        match self.exchange(&line) {
+            Ok(RpcResponse::Error { code, message }) => Err(ClientError::Daemon { code, message }),
+            Ok(RpcResponse::ShuttingDown) => {
+                self.mark_broken();
+                Err(ClientError::ShuttingDown)
+            }
+            Ok(resp) => Ok(resp),
+            Err(e) => {
+                self.mark_broken();
+                Err(e)
+            }
+        }
+    }
+
+    fn exchange(&mut self, line: &[u8]) -> Result<RpcResponse, ClientError> {
+        self.writer.write_all(line)?;
+        let mut buf = String::new();
+        if self.reader.read_line(&mut buf)? == 0 {
+            return Err(ClientError::Closed);
+        }
+        Ok(serde_json::from_str(buf.trim_end())?)
+    }
+
+    fn mark_broken(&mut self) {
+        self.broken = true;
+        // Best effort: the peer may already be gone.
+        let _ = self.writer.shutdown(std::net::Shutdown::Both);
+    }
+
+    /// Present the shared secret; required first when the daemon runs with
+    /// `--require-auth`.
+    pub fn auth(&mut self, token: &str) -> Result<(), ClientError> {
+        let req = RpcRequest::Auth {
+            token: token.to_owned(),
+        };
+        match self.call(&req)? {
+            RpcResponse::Authenticated => Ok(()),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// Liveness probe; returns the daemon's uptime in seconds.
+    pub fn ping(&mut self) -> Result<u64, ClientError> {
+        match self.call(&RpcRequest::Ping)? {
+            RpcResponse::Pong { uptime_s } => Ok(uptime_s),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// Daemon identity, for compatibility checks.
+    pub fn version(&mut self) -> Result<DaemonVersion, ClientError> {
+        match self.call(&RpcRequest::Version)? {
+            RpcResponse::Version {
+                daemon,
+                protocol,
+                started_at,
+                pid,
+            } => Ok(DaemonVersion {
+                daemon,
+                protocol,
+                started_at,
+                pid,
+            }),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// Register an architector; returns its id.
+    pub fn architect_register(
+        &mut self,
+        repo: &str,
+        milestone_id: &str,
+        issue_url: &str,
+    ) -> Result<String, ClientError> {
+        let req = RpcRequest::ArchitectRegister {
+            repo: repo.to_owned(),
+            milestone_id: milestone_id.to_owned(),
+            issue_url: issue_url.to_owned(),
+        };
+        match self.call(&req)? {
+            RpcResponse::ArchitectorRegistered { architector_id } => Ok(architector_id),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// Tasks attached to an architector.
+    pub fn task_list(&mut self, architector_id: &str) -> Result<Vec<Task>, ClientError> {
+        let req = RpcRequest::TaskList {
+            architector_id: architector_id.to_owned(),
+        };
+        match self.call(&req)? {
+            RpcResponse::TaskList { tasks } => Ok(tasks),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// One task by id.
+    pub fn task_get(&mut self, task_id: &str) -> Result<Task, ClientError> {
+        let req = RpcRequest::TaskGet {
+            task_id: task_id.to_owned(),
+        };
+        match self.call(&req)? {
+            RpcResponse::Task(task) => Ok(*task),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// The daemon's effective configuration, as a JSON object.
+    pub fn get_config(&mut self) -> Result<serde_json::Value, ClientError> {
+        match self.call(&RpcRequest::GetConfig)? {
+            RpcResponse::Config { config } => Ok(config),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// Recent internal warnings, oldest first.
+    pub fn diagnostics(&mut self) -> Result<Vec<Diagnostic>, ClientError> {
+        match self.call(&RpcRequest::Diagnostics)? {
+            RpcResponse::Diagnostics { entries } => Ok(entries),
+            other => Err(unexpected(other)),
+        }
+    }
+}
+
+/// Capped exponential backoff between reconnect attempts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Backoff {
+    /// Delay after the first failed attempt; doubles after each one.
+    pub initial: Duration,
+    /// Upper bound on a single delay.
+    pub max: Duration,
+    /// Tries per call, counting the first, before a lost connection is
+    /// reported to the caller.
+    pub attempts: u32,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self {
+            initial: Duration::from_millis(50),
+            max: Duration::from_secs(2),
+            attempts: 10,
+        }
+    }
+}
+
+impl Backoff {
+    /// Delay after failed attempt `n` (0-based).
+    pub fn delay(&self, n: u32) -> Duration {
+        self.initial
+            .saturating_mul(1u32.checked_shl(n).unwrap_or(u32::MAX))
+            .min(self.max)
+    }
+}
+
+/// A [`Client`] that reconnects, with backoff, when the daemon goes away.
+///
+/// Connects lazily. A call that fails because the connection is lost (a
+/// broken socket, a refused connect, a `ShuttingDown` notice) is retried on
+/// a fresh connection after the next backoff delay, until
+/// [`Backoff::attempts`] tries are spent. That includes connects that land
+/// in a stopping daemon's backlog and are then reset. A request may reach
+/// the daemon twice if the old one died after receiving it; fine for reads,
+/// worth bearing in mind for anything that creates state.
+///
+/// A timeout or undecodable reply is not retried: the daemon is still there
+/// and may have acted on the request. The connection is dropped, the error
+/// returned, and the next call starts on a fresh connection.
+#[derive(Debug)]
+pub struct ReconnectingClient {
+    path: PathBuf,
+    token: Option<String>,
+    timeout: Option<Duration>,
+    backoff: Backoff,
+    conn: Option<Client>,
+}
+
+impl ReconnectingClient {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            token: None,
+            timeout: None,
+            backoff: Backoff::default(),
+            conn: None,
+        }
+    }
+
+    /// Authenticate every new connection with `token`.
+    pub fn with_token(mut self, token: impl Into<String>) -> Self {
+        self.token = Some(token.into());
+        self
+    }
+
+    /// Socket timeout applied to every new connection.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub fn with_backoff(mut self, backoff: Backoff) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    /// Run `op` against a live connection, (re)connecting as needed and
+    /// backing off between tries while the connection keeps getting lost.
+    pub fn with_client<T>(
+        &mut self,
+        mut op: impl FnMut(&mut Client) -> Result<T, ClientError>,
+    ) -> Result<T, ClientError> {
+        let mut attempt = 0;
+        loop {
+            let result = match &mut self.conn {
+                Some(conn) => op(conn),
+                None => match self.connect_once() {
+                    Ok(conn) => op(self.conn.insert(conn)),
+                    Err(e) => Err(e),
+                },
+            };
+            match result {
+                Err(e) if e.is_connection_lost() => {
+                    self.conn = None;
+                    if attempt + 1 >= self.backoff.attempts {
+                        return Err(e);
+                    }
+                    thread::sleep(self.backoff.delay(attempt));
+                    attempt += 1;
+                }
+                Err(e @ (ClientError::Io(_) | ClientError::Decode(_))) => {
+                    // Out of step with the daemon; never reuse it.
+                    self.conn = None;
+                    return Err(e);
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Send `req` and return the reply; see [`Client::call`].
+    pub fn call(&mut self, req: &RpcRequest) -> Result<RpcResponse, ClientError> {
+        self.with_client(|c| c.call(req))
+    }
+
+    fn connect_once(&self) -> Result<Client, ClientError> {
+        let mut client = Client::connect(&self.path)?;
+        client.set_timeout(self.timeout)?;
+        if let Some(token) = &self.token {
+            client.auth(token)?;
+        }
+        Ok(client)
+    }
+}
+
+fn unexpected(resp: RpcResponse) -> ClientError {
+    ClientError::Unexpected(Box::new(resp))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+
+    /// Run a fake daemon on one end of a socket pair that answers each
+    /// request line with the next canned response.
+    fn scripted(responses: Vec<RpcResponse>) -> (Client, thread::JoinHandle<Vec<RpcRequest>>) {
+        let (ours, theirs) = UnixStream::pair().unwrap();
+        let server = thread::spawn(move || {
+            let mut reader = BufReader::new(theirs.try_clone().unwrap());
+            let mut writer = theirs;
+            let mut seen = Vec::new();
+            for resp in responses {
+                let mut line = String::new();
+                if reader.read_line(&mut line).unwrap() == 0 {
+                    break;
+                }
+                seen.push(serde_json::from_str(line.trim_end()).unwrap());
+                let mut out = serde_json::to_vec(&resp).unwrap();
+                out.push(b'\n');
+                writer.write_all(&out).unwrap();
+            }
+            seen
+        });
+        (Client::from_stream(ours).unwrap(), server)
+    }
+
+    #[test]
+    fn typed_helpers_unwrap_matching_responses() {
+        let (mut client, server) = scripted(vec![
+            RpcResponse::Pong { uptime_s: 7 },
+            RpcResponse::Version {
+                daemon: "0.1.0".to_owned(),
+                protocol: 1,
+                started_at: 1_700_000_000,
+                pid: 42,
+            },
+        ]);
+        assert_eq!(client.ping().unwrap(), 7);
+        assert_eq!(client.version().unwrap().pid, 42);
+        drop(client);
+        assert_eq!(
+            server.join().unwrap(),
+            vec![RpcRequest::Ping, RpcRequest::Version]
+        );
+    }
+
+    #[test]
+    fn daemon_errors_and_mismatches_are_typed() {
+        let (mut client, _server) = scripted(vec![
+            RpcResponse::Error {
+                code: ErrorCode::NotImplemented,
+                message: "later".to_owned(),
+            },
+            RpcResponse::Authenticated,
+            RpcResponse::ShuttingDown,
+        ]);
+        assert!(matches!(
+            client.task_get("M1-T1"),
+            Err(ClientError::Daemon {
+                code: ErrorCode::NotImplemented,
+                ..
+            })
+        ));
+        assert!(matches!(client.ping(), Err(ClientError::Unexpected(_))));
+        assert!(matches!(client.ping(), Err(ClientError::ShuttingDown)));
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_cap() {
+        let b = Backoff {
+            initial: Duration::from_millis(100),
+            max: Duration::from_millis(500),
+            attempts: 5,
+        };
+        let delays: Vec<_> = (0..5).map(|n| b.delay(n).as_millis()).collect();
+        assert_eq!(delays, [100, 200, 400, 500, 500]);
+        assert_eq!(b.delay(64), b.max);
+    }
+
+    #[test]
+    fn reconnecting_client_gives_up_after_its_attempts() {
+        let dir = std::env::temp_dir().join(format!("ca-client-test-{}", std::process::id()));
+        let mut client = ReconnectingClient::new(dir.join("absent.sock")).with_backoff(Backoff {
+            initial: Duration::from_millis(1),
+            max: Duration::from_millis(1),
+            attempts: 3,
+        });
+        let err = client.call(&RpcRequest::Ping).unwrap_err();
+        assert!(err.is_connection_lost(), "got {err:?}");
+    }
+
+    #[test]
+    fn reconnecting_client_rides_out_repeated_resets() {
+        let path =
+            std::env::temp_dir().join(format!("ca-client-reset-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = std::os::unix::net::UnixListener::bind(&path).unwrap();
+        // Like a stopping daemon's backlog: the first connections are
+        // accepted and dropped, even after the ping has been sent.
+        let server = thread::spawn(move || {
+            for _ in 0..2 {
+                drop(listener.accept().unwrap());
+            }
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            let mut writer = stream;
+            writer
+                .write_all(b"{\"type\":\"pong\",\"uptime_s\":1}\n")
+                .unwrap();
+        });
+
+        let mut client = ReconnectingClient::new(&path).with_backoff(Backoff {
+            initial: Duration::from_millis(1),
+            max: Duration::from_millis(5),
+            attempts: 5,
+        });
+        assert_eq!(client.with_client(Client::ping).unwrap(), 1);
+        server.join().unwrap();
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn late_reply_after_timeout_is_never_handed_to_the_next_call() {
+        let (ours, theirs) = UnixStream::pair().unwrap();
+        let server = thread::spawn(move || {
+            let mut reader = BufReader::new(theirs.try_clone().unwrap());
+            let mut writer = theirs;
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            thread::sleep(Duration::from_millis(200));
+            // The client has given up on this reply by now.
+            let _ = writer.write_all(b"{\"type\":\"pong\",\"uptime_s\":1}\n");
+        });
+        let mut client = Client::from_stream(ours).unwrap();
+        client.set_timeout(Some(Duration::from_millis(50))).unwrap();
+
+        match client.ping() {
+            Err(ClientError::Io(e)) => assert!(
+                matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ),
+                "got {e:?}"
+            ),
+            other => panic!("expected a timeout, got {other:?}"),
+        }
+        server.join().unwrap();
+        assert!(matches!(client.version(), Err(ClientError::Closed)));
+        assert!(matches!(client.ping(), Err(ClientError::Closed)));
+    }
+
+    #[test]
+    fn garbled_reply_closes_the_client() {
+        let (ours, theirs) = UnixStream::pair().unwrap();
+        let server = thread::spawn(move || {
+            let mut reader = BufReader::new(theirs.try_clone().unwrap());
+            let mut writer = theirs;
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            writer
+                .write_all(b"not json\n{\"type\":\"pong\",\"uptime_s\":1}\n")
+                .unwrap();
+        });
+        let mut client = Client::from_stream(ours).unwrap();
+        assert!(matches!(client.ping(), Err(ClientError::Decode(_))));
+        server.join().unwrap();
+        assert!(matches!(client.ping(), Err(ClientError::Closed)));
+    }
+
+    #[test]
+    fn reconnecting_client_drops_a_timed_out_connection() {
+        let path =
+            std::env::temp_dir().join(format!("ca-client-timeout-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = std::os::unix::net::UnixListener::bind(&path).unwrap();
+        let server = thread::spawn(move || {
+            // First connection answers too late; the second one promptly.
+            let replies: [(u64, &[u8]); 2] = [
+                (200, b"{\"type\":\"pong\",\"uptime_s\":1}\n"),
+                (0, b"{\"type\":\"pong\",\"uptime_s\":2}\n"),
+            ];
+            let handlers: Vec<_> = replies
+                .into_iter()
+                .map(|(delay, reply)| {
+                    let (stream, _) = listener.accept().unwrap();
+                    thread::spawn(move || {
+                        let mut reader = BufReader::new(stream.try_clone().unwrap());
+                        let mut line = String::new();
+                        reader.read_line(&mut line).unwrap();
+                        thread::sleep(Duration::from_millis(delay));
+                        let mut writer = stream;
+                        let _ = writer.write_all(reply);
+                    })
+                })
+                .collect();
+            let accepted = handlers.len();
+            for h in handlers {
+                h.join().unwrap();
+            }
+            accepted
+        });
+
+        let mut client = ReconnectingClient::new(&path)
+            .with_timeout(Duration::from_millis(50))
+            .with_backoff(Backoff {
+                initial: Duration::from_millis(1),
+                max: Duration::from_millis(5),
+                attempts: 5,
+            });
+        // The timeout is reported, not retried...
+        match client.with_client(Client::ping) {
+            Err(ClientError::Io(e)) => assert!(
+                matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ),
+                "got {e:?}"
+            ),
+            other => panic!("expected a timeout, got {other:?}"),
+        }
+        // ...and the late pong never reaches the next call.
+        assert_eq!(client.with_client(Client::ping).unwrap(), 2);
+        assert_eq!(server.join().unwrap(), 2);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn eof_is_closed() {
+        let (mut client, server) = scripted(vec![]);
+        server.join().unwrap();
+        assert!(matches!(
+            client.ping(),
+            Err(ClientError::Closed | ClientError::Io(_))
+        ));
+    }
+}
@@ -0,0 +1,51 @@
+//! Helpers for echoing untrusted strings to a terminal.
+//!
+//! Names and subjects come from tmux, git and clients verbatim. Printing an
+//! embedded escape sequence would let it recolour, move the cursor or retitle
+//! the user's terminal, so output paths pass them through
+//! [`sanitize_for_display`]. Models keep the raw value.
+
+use std::borrow::Cow;
+
+/// Replace control characters (C0, DEL, C1) with visible Rust-style escapes,
+/// e.g. ESC becomes `\u{1b}` and a newline becomes `\n`. Borrows when there
+/// is nothing to replace.
+pub fn sanitize_for_display(raw: &str) -> Cow<'_, str> {
+    if !raw.chars().any(char::is_control) {
+        return Cow::Borrowed(raw);
+    }
+    let mut out = String::with_capacity(raw.len() + 8);
+    for c in raw.chars() {
+        if c.is_control() {
+            out.extend(c.escape_default());
+        } else {
+            out.push(c);
+        }
+    }
+    Cow::Owned(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plain_text_is_borrowed_unchanged() {
+        let s = "M1-T4 — add client ✓";
+        assert!(matches!(sanitize_for_display(s), Cow::Borrowed(b) if b == s));
+    }
+
+    #[test]
+    fn escape_sequences_are_neutralised() {
+        assert_eq!(
+            sanitize_for_display("work\x1b[31mred\x1b]0;pwned\x07"),
+            "work\\u{1b}[31mred\\u{1b}]0;pwned\\u{7}"
+        );
+    }
+
+    #[test]
+    fn whitespace_controls_and_c1_are_escaped() {
+        assert_eq!(sanitize_for_display("a\nb\tc\rd"), "a\\nb\\tc\\rd");
+        assert_eq!(sanitize_for_display("x\u{7f}y\u{9b}z"), "x\\u{7f}y\\u{9b}z");
+    }
+}
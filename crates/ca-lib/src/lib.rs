@@ -5,17 +5,22 @@
 //! SQLite store.
 
 pub mod architector;
+#[cfg(unix)]
+pub mod client;
 pub mod commit;
 pub mod critique;
+pub mod display;
 pub mod review;
 pub mod rpc;
 pub mod task;
 
 pub use architector::{Architector, ArchitectorOutcome, ArchitectorState};
+#[cfg(unix)]
+pub use client::{Backoff, Client, ClientError, ReconnectingClient};
 pub use commit::Commit;
 pub use critique::{CritiqueAxes, CritiqueResult, Verdict};
 pub use review::{Finding, ReviewResult, ReviewerKind, Severity};
-pub use rpc::{RpcRequest, RpcResponse};
+pub use rpc::{Diagnostic, DiagnosticKind, ErrorCode, Framing, RpcRequest, RpcResponse};
 pub use task::{Task, TaskStatus};
 
 /// Returns the package version string for `ca-lib`.
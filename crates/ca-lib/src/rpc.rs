@@ -12,6 +12,13 @@ use crate::Task;
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(tag = "type", rename_all = "snake_case")]
 pub enum RpcRequest {
+    /// Present the shared secret. Must be the first request on a connection
+    /// when the daemon runs with `--require-auth`; accepted (and a no-op)
+    /// otherwise.
+    Auth { token: String },
+    /// Switch this connection's framing. The reply uses the current framing;
+    /// every message after it uses the new one.
+    SetFraming { framing: Framing },
     /// Liveness probe.
     Ping,
     /// Daemon version + protocol version handshake.
@@ -26,24 +33,152 @@ pub enum RpcRequest {
     TaskList { architector_id: String },
     /// Fetch one task by id.
     TaskGet { task_id: String },
+    /// Recent internal warnings (rejected frames, accept errors, throttled
+    /// or turned-away clients), oldest first.
+    Diagnostics,
+    /// The daemon's effective configuration after flags, env vars and
+    /// defaults are merged.
+    GetConfig,
+}
+
+impl RpcRequest {
+    /// Every wire `type` tag, in declaration order.
+    pub const KINDS: &[&str] = &[
+        "auth",
+        "set_framing",
+        "ping",
+        "version",
+        "architect_register",
+        "task_list",
+        "task_get",
+        "diagnostics",
+        "get_config",
+    ];
+
+    /// The wire `type` tag, for logging without the payload (which may hold
+    /// a token).
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Auth { .. } => "auth",
+            Self::SetFraming { .. } => "set_framing",
+            Self::Ping => "ping",
+            Self::Version => "version",
+            Self::ArchitectRegister { .. } => "architect_register",
+            Self::TaskList { .. } => "task_list",
+            Self::TaskGet { .. } => "task_get",
+            Self::Diagnostics => "diagnostics",
+            Self::GetConfig => "get_config",
+        }
+    }
 }
 
 /// All responses the daemon can return. Tagged in JSON via `"type"`.
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(tag = "type", rename_all = "snake_case")]
 pub enum RpcResponse {
+    /// Connection is authenticated; other requests may follow.
+    Authenticated,
+    /// Framing switched; applies from the next message on.
+    FramingSet { framing: Framing },
     /// Pong + server uptime in seconds.
     Pong { uptime_s: u64 },
-    /// Daemon version + protocol version.
-    Version { daemon: String, protocol: u32 },
+    /// Daemon version + protocol version, plus when the daemon started
+    /// (unix seconds) and its pid. The last two read as 0 from daemons that
+    /// predate them.
+    Version {
+        daemon: String,
+        protocol: u32,
+        #[serde(default)]
+        started_at: i64,
+        #[serde(default)]
+        pid: u32,
+    },
     /// Architector created and persisted.
     ArchitectorRegistered { architector_id: String },
     /// Task list for an architector.
     TaskList { tasks: Vec<Task> },
     /// One task.
     Task(Box<Task>),
-    /// Generic error response.
-    Error { message: String },
+    /// Reply to `Diagnostics`.
+    Diagnostics { entries: Vec<Diagnostic> },
+    /// Reply to `GetConfig`: setting name → resolved value. Secrets are
+    /// never included; the token appears only as its file path.
+    Config { config: serde_json::Value },
+    /// Unsolicited push: the daemon is stopping and closes the connection
+    /// right after this message.
+    ShuttingDown,
+    /// Generic error response. `code` is machine-readable; `message` is for
+    /// humans and may change between releases. Daemons that predate `code`
+    /// only rejected malformed requests, so a missing one reads as
+    /// `bad_request`.
+    Error {
+        #[serde(default = "bad_request")]
+        code: ErrorCode,
+        message: String,
+    },
+}
+
+/// How messages are delimited on the socket.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Framing {
+    /// One JSON document per `\n`-terminated line. Default for every new
+    /// connection.
+    #[default]
+    Line,
+    /// `u32` big-endian byte count followed by that many bytes of JSON.
+    LengthPrefixed,
+}
+
+/// Machine-readable error categories carried by [`RpcResponse::Error`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    /// Request line was not valid JSON or not a known request.
+    BadRequest,
+    /// Request is well-formed but the daemon does not serve it yet.
+    NotImplemented,
+    /// Connection exceeded its request budget; retry after a short pause.
+    RateLimited,
+    /// Missing or wrong auth token. The daemon closes the connection.
+    Unauthorized,
+    /// The daemon is at its connection limit. It closes the connection;
+    /// retry later.
+    Busy,
+}
+
+/// One internal warning kept for `Diagnostics`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Diagnostic {
+    /// Unix seconds of the first occurrence.
+    pub at: i64,
+    pub kind: DiagnosticKind,
+    pub message: String,
+    /// Back-to-back occurrences from one connection folded into this entry.
+    #[serde(default = "one")]
+    pub count: u32,
+}
+
+fn one() -> u32 {
+    1
+}
+
+fn bad_request() -> ErrorCode {
+    ErrorCode::BadRequest
+}
+
+/// What raised a [`Diagnostic`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DiagnosticKind {
+    /// A client frame that did not decode to a request.
+    BadRequest,
+    /// A request dropped by the per-connection rate limit.
+    RateLimited,
+    /// A client turned away at the connection limit.
+    Busy,
+    /// The listener failed to accept a connection.
+    Accept,
 }
 
 #[cfg(test)]
@@ -60,6 +195,39 @@ mod tests {
         assert_eq!(r, parsed);
     }
 
+    #[test]
+    fn rpc_request_kind_matches_wire_tag() {
+        let reqs = [
+            RpcRequest::Auth {
+                token: "t".to_owned(),
+            },
+            RpcRequest::SetFraming {
+                framing: Framing::Line,
+            },
+            RpcRequest::Ping,
+            RpcRequest::Version,
+            RpcRequest::ArchitectRegister {
+                repo: String::new(),
+                milestone_id: String::new(),
+                issue_url: String::new(),
+            },
+            RpcRequest::TaskList {
+                architector_id: String::new(),
+            },
+            RpcRequest::TaskGet {
+                task_id: String::new(),
+            },
+            RpcRequest::Diagnostics,
+            RpcRequest::GetConfig,
+        ];
+        let kinds: Vec<_> = reqs.iter().map(RpcRequest::kind).collect();
+        assert_eq!(kinds, RpcRequest::KINDS);
+        for req in reqs {
+            let json = serde_json::to_value(&req).unwrap();
+            assert_eq!(json["type"], req.kind());
+        }
+    }
+
     #[test]
     fn rpc_request_with_fields_roundtrip() {
         let r = RpcRequest::ArchitectRegister {
@@ -96,6 +264,32 @@ mod tests {
         assert_eq!(r, parsed);
     }
 
+    #[test]
+    fn rpc_response_error_carries_snake_case_code() {
+        let r = RpcResponse::Error {
+            code: ErrorCode::RateLimited,
+            message: "slow down".to_owned(),
+        };
+        let json = serde_json::to_string(&r).unwrap();
+        assert!(json.contains("\"code\":\"rate_limited\""), "json: {json}");
+        let parsed: RpcResponse = serde_json::from_str(&json).unwrap();
+        assert_eq!(r, parsed);
+    }
+
+    #[test]
+    fn set_framing_request_wire_shape() {
+        let r = RpcRequest::SetFraming {
+            framing: Framing::LengthPrefixed,
+        };
+        let json = serde_json::to_string(&r).unwrap();
+        assert_eq!(
+            json,
+            r#"{"type":"set_framing","framing":"length_prefixed"}"#
+        );
+        let parsed: RpcRequest = serde_json::from_str(&json).unwrap();
+        assert_eq!(r, parsed);
+    }
+
     #[test]
     fn rpc_unknown_variant_errors() {
         // Required by M0-T3 spec: unknown discriminator produces an Err, not Ok.
@@ -107,6 +301,35 @@ mod tests {
         );
     }
 
+    #[test]
+    fn version_from_an_older_daemon_still_parses() {
+        let old = r#"{"type":"version","daemon":"0.1.0","protocol":1}"#;
+        let parsed: RpcResponse = serde_json::from_str(old).unwrap();
+        assert_eq!(
+            parsed,
+            RpcResponse::Version {
+                daemon: "0.1.0".to_owned(),
+                protocol: 1,
+                started_at: 0,
+                pid: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn error_without_code_reads_as_bad_request() {
+        // As sent by daemons from before error codes existed.
+        let old = r#"{"type":"error","message":"unknown request"}"#;
+        let parsed: RpcResponse = serde_json::from_str(old).unwrap();
+        assert_eq!(
+            parsed,
+            RpcResponse::Error {
+                code: ErrorCode::BadRequest,
+                message: "unknown request".to_owned(),
+            }
+        );
+    }
+
     #[test]
     fn rpc_missing_tag_errors() {
         // Defensive: no "type" field → Err.